@@ -0,0 +1,119 @@
+// How the servers of one interface are combined into its bandwidth. Every strategy is
+// expressed as a share per server, so the export of who contributed is the same for all.
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Aggregate {
+    /// Arithmetic mean of all servers that answered
+    Mean,
+    /// Median of the servers; one slow or far-away server does not move it
+    Median,
+    /// Fastest server, for links whose servers are the bottleneck rather than the link
+    Max,
+    /// Mean without the slowest and fastest quarter of the servers (at least one each from 3 on)
+    TrimmedMean,
+    /// Mean weighted by the `priority` of each server
+    Weighted,
+}
+
+// Combine the (bandwidth, priority) of the servers that answered; returns the aggregate
+// and the share of each server in it, in input order. None when nothing answered.
+pub fn combine(aggregate: Aggregate, samples: &[(f64, f64)]) -> Option<(f64, Vec<f64>)> {
+    if samples.is_empty() {
+        return None;
+    }
+    let n = samples.len();
+    // Indices from slowest to fastest
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| samples[a].0.total_cmp(&samples[b].0));
+
+    let mut shares = vec![0.0; n];
+    match aggregate {
+        Aggregate::Mean => shares.fill(1.0 / n as f64),
+        Aggregate::Median if n % 2 == 1 => shares[order[n / 2]] = 1.0,
+        Aggregate::Median => {
+            shares[order[n / 2 - 1]] = 0.5;
+            shares[order[n / 2]] = 0.5;
+        }
+        Aggregate::Max => shares[order[n - 1]] = 1.0,
+        Aggregate::TrimmedMean => {
+            let cut = if n >= 3 { (n / 4).max(1) } else { 0 };
+            let kept = &order[cut..n - cut];
+            for &i in kept {
+                shares[i] = 1.0 / kept.len() as f64;
+            }
+        }
+        Aggregate::Weighted => {
+            let total: f64 = samples.iter().map(|&(_, priority)| priority).sum();
+            for (share, &(_, priority)) in shares.iter_mut().zip(samples) {
+                *share = priority / total;
+            }
+        }
+    }
+
+    let value = shares
+        .iter()
+        .zip(samples)
+        .map(|(share, &(bps, _))| share * bps)
+        .sum();
+    Some((value, shares))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bps(aggregate: Aggregate, values: &[f64]) -> f64 {
+        let samples: Vec<(f64, f64)> = values.iter().map(|&v| (v, 1.0)).collect();
+        combine(aggregate, &samples).unwrap().0
+    }
+
+    #[test]
+    fn nothing_answered() {
+        assert_eq!(combine(Aggregate::Mean, &[]), None);
+    }
+
+    #[test]
+    fn mean_median_and_max() {
+        let values = [400.0, 100.0, 300.0, 200.0];
+
+        assert_eq!(bps(Aggregate::Mean, &values), 250.0);
+        assert_eq!(bps(Aggregate::Median, &values), 250.0);
+        assert_eq!(bps(Aggregate::Median, &values[..3]), 300.0);
+        assert_eq!(bps(Aggregate::Max, &values), 400.0);
+    }
+
+    #[test]
+    fn trimmed_mean_drops_the_extremes_from_three_servers_on() {
+        assert_eq!(bps(Aggregate::TrimmedMean, &[100.0]), 100.0);
+        assert_eq!(bps(Aggregate::TrimmedMean, &[100.0, 300.0]), 200.0);
+        assert_eq!(bps(Aggregate::TrimmedMean, &[900.0, 100.0, 300.0]), 300.0);
+        assert_eq!(
+            bps(Aggregate::TrimmedMean, &[900.0, 100.0, 300.0, 200.0]),
+            250.0
+        );
+    }
+
+    #[test]
+    fn weighted_by_priority() {
+        let (value, shares) = combine(Aggregate::Weighted, &[(100.0, 1.0), (200.0, 3.0)]).unwrap();
+
+        assert_eq!(value, 175.0);
+        assert_eq!(shares, [0.25, 0.75]);
+    }
+
+    #[test]
+    fn shares_follow_input_order() {
+        let samples = [(300.0, 1.0), (100.0, 1.0), (200.0, 1.0)];
+
+        assert_eq!(
+            combine(Aggregate::Median, &samples).unwrap().1,
+            [0.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            combine(Aggregate::Max, &samples).unwrap().1,
+            [1.0, 0.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Versioned JSON API for the correction factors, mounted under /api/v1.
+//
+//   GET    /api/v1/corrections              default and per-interface factors
+//   GET    /api/v1/corrections/{nic}        effective factor of one interface
+//   PUT    /api/v1/corrections/{nic}        {"factor": 0.8} sets the interface factor (POST too)
+//   DELETE /api/v1/corrections/{nic}        back to the default factor
+//   GET    /api/v1/default-correction       the default factor
+//   PUT    /api/v1/default-correction       {"factor": 0.8} sets the default (POST too)
+//
+// `nic` may be a WAN alias (wan0) or an interface name (eth0).
+// Errors are answered as {"error": {"code": "...", "message": "..."}}.
+use axum::{
+    extract::{rejection::JsonRejection, ConnectInfo, Path},
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use crate::corrections::{Correction, Corrections};
+use crate::{map_wan_to_interface, update_corrections, CORRECTIONS, WAN_ALIASES};
+
+pub fn router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new()
+        .route("/corrections", get(list_corrections))
+        .route(
+            "/corrections/:nic",
+            get(get_correction)
+                .put(set_correction)
+                .post(set_correction)
+                .delete(reset_correction),
+        )
+        .route(
+            "/default-correction",
+            get(get_default).put(set_default).post(set_default),
+        )
+}
+
+#[derive(Deserialize)]
+struct SetCorrection {
+    factor: f64,
+}
+
+// Effective factor of one interface
+#[derive(Serialize)]
+struct CorrectionEntry {
+    // Name as given in the request
+    nic: String,
+    interface: String,
+    // Logical WAN name, the interface name when it has no alias
+    wan: String,
+    // True when the interface has no factor of its own and uses the default
+    inherited: bool,
+    #[serde(flatten)]
+    correction: Correction,
+}
+
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: String) -> Self {
+        ApiError {
+            status,
+            code,
+            message,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": { "code": self.code, "message": self.message }
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_body", rejection.body_text())
+    }
+}
+
+fn entry(nic: String, interface: String, corrections: &Corrections) -> CorrectionEntry {
+    let own = corrections.interfaces.get(&interface);
+    let wan = WAN_ALIASES.lock().unwrap().wan(&interface).to_string();
+    CorrectionEntry {
+        nic,
+        interface,
+        wan,
+        inherited: own.is_none(),
+        correction: own.unwrap_or(&corrections.default).clone(),
+    }
+}
+
+fn parse_factor(body: Result<Json<SetCorrection>, JsonRejection>) -> Result<f64, ApiError> {
+    let Json(body) = body?;
+    if body.factor > 0.0 && body.factor.is_finite() {
+        Ok(body.factor)
+    } else {
+        Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_factor",
+            format!("factor must be greater than 0, got {}", body.factor),
+        ))
+    }
+}
+
+// Apply and persist a change; the in-memory value is kept even if saving fails
+async fn persist(change: impl FnOnce(&mut Corrections)) -> Result<(), ApiError> {
+    update_corrections(change).await.map_err(|e| {
+        eprintln!("Failed to persist correction factors: {}", e);
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "not_persisted",
+            format!("Correction applied but not saved to the state file: {}", e),
+        )
+    })
+}
+
+async fn list_corrections() -> Json<Corrections> {
+    Json(CORRECTIONS.lock().unwrap().clone())
+}
+
+async fn get_correction(Path(nic): Path<String>) -> Json<CorrectionEntry> {
+    let interface = map_wan_to_interface(&nic);
+    Json(entry(nic, interface, &CORRECTIONS.lock().unwrap()))
+}
+
+async fn set_correction(
+    method: Method,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(nic): Path<String>,
+    body: Result<Json<SetCorrection>, JsonRejection>,
+) -> Result<Json<CorrectionEntry>, ApiError> {
+    let factor = parse_factor(body)?;
+    let interface = map_wan_to_interface(&nic);
+    let source = format!("{} /api/v1/corrections from {}", method, peer.ip());
+    let correction = Correction::new(factor, source);
+
+    persist(|c| {
+        c.interfaces.insert(interface.clone(), correction);
+    })
+    .await?;
+    Ok(Json(entry(nic, interface, &CORRECTIONS.lock().unwrap())))
+}
+
+async fn reset_correction(Path(nic): Path<String>) -> Result<Json<CorrectionEntry>, ApiError> {
+    let interface = map_wan_to_interface(&nic);
+    if !CORRECTIONS
+        .lock()
+        .unwrap()
+        .interfaces
+        .contains_key(&interface)
+    {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!(
+                "{} ({}) has no correction factor of its own",
+                nic, interface
+            ),
+        ));
+    }
+
+    persist(|c| {
+        c.interfaces.remove(&interface);
+    })
+    .await?;
+    Ok(Json(entry(nic, interface, &CORRECTIONS.lock().unwrap())))
+}
+
+async fn get_default() -> Json<Correction> {
+    Json(CORRECTIONS.lock().unwrap().default.clone())
+}
+
+async fn set_default(
+    method: Method,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    body: Result<Json<SetCorrection>, JsonRejection>,
+) -> Result<Json<Correction>, ApiError> {
+    let factor = parse_factor(body)?;
+    let source = format!("{} /api/v1/default-correction from {}", method, peer.ip());
+    let correction = Correction::new(factor, source);
+
+    persist(|c| c.default = correction.clone()).await?;
+    Ok(Json(correction))
+}
@@ -0,0 +1,750 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+use crate::aggregate::Aggregate;
+use crate::corrections::DEFAULT_STATE_FILE;
+use crate::discovery::Discovery;
+use crate::model::Model;
+use crate::probe::{ProbeMode, ProbeOptions, Route};
+use crate::stats::StatsSettings;
+use crate::wan::WanAliases;
+
+pub const DEFAULT_METRICS_LISTEN: &str = "0.0.0.0:59121";
+pub const DEFAULT_API_LISTEN: &str = "0.0.0.0:32600";
+
+// Probe parameters that can be set globally, per interface and per server.
+// The most specific value wins: CLI > server > interface > file defaults > built-in defaults.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ProbeSettings {
+    // Seconds between two probes of the same interface/server pair
+    pub interval: Option<f64>,
+    pub probe: Option<ProbeMode>,
+    // Object fetched by the HTTP probe
+    pub url: Option<String>,
+    // Bulk/HTTP/reflector transfer limits
+    pub bytes: Option<u64>,
+    pub duration: Option<f64>,
+    // Bulk probe payload sent after connecting
+    pub request: Option<String>,
+    // Parallel downloads of the responsiveness probe
+    pub flows: Option<usize>,
+    // TCP efficiency factor applied by the buffer model
+    pub efficiency: Option<f64>,
+    // Bandwidth model behind `tcp_bandwidth_bps`, by probe type when unset
+    pub model: Option<Model>,
+    pub connect_timeout: Option<f64>,
+    pub probe_timeout: Option<f64>,
+    // Refuse to probe when the socket cannot be bound to the interface
+    pub strict_bind: Option<bool>,
+    // Weight of the server in the `weighted` aggregate of its interface
+    pub priority: Option<f64>,
+}
+
+impl ProbeSettings {
+    // Fill every unset field from `fallback`
+    fn or(&self, fallback: &ProbeSettings) -> ProbeSettings {
+        ProbeSettings {
+            interval: self.interval.or(fallback.interval),
+            probe: self.probe.or(fallback.probe),
+            url: self.url.clone().or_else(|| fallback.url.clone()),
+            bytes: self.bytes.or(fallback.bytes),
+            duration: self.duration.or(fallback.duration),
+            request: self.request.clone().or_else(|| fallback.request.clone()),
+            flows: self.flows.or(fallback.flows),
+            efficiency: self.efficiency.or(fallback.efficiency),
+            model: self.model.or(fallback.model),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+            probe_timeout: self.probe_timeout.or(fallback.probe_timeout),
+            strict_bind: self.strict_bind.or(fallback.strict_bind),
+            priority: self.priority.or(fallback.priority),
+        }
+    }
+
+    fn builtin() -> ProbeSettings {
+        ProbeSettings {
+            interval: Some(1.0),
+            probe: Some(ProbeMode::Estimate),
+            url: None,
+            bytes: Some(10_000_000),
+            duration: Some(5.0),
+            request: None,
+            flows: Some(4),
+            // TCP typically achieves 70-90% efficiency, 0.75 is a reasonable default
+            efficiency: Some(0.75),
+            model: None,
+            connect_timeout: Some(5.0),
+            probe_timeout: Some(10.0),
+            strict_bind: Some(false),
+            priority: Some(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListenConfig {
+    pub metrics: Option<SocketAddr>,
+    pub api: Option<SocketAddr>,
+}
+
+// `[discovery]`: probe the interfaces found via netlink instead of a fixed list
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub enabled: Option<bool>,
+    // Interface name patterns with `*` and `?`
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    // Link types (ether, ppp, none, ...) or kinds (vlan, wireguard, ...)
+    pub types: Option<Vec<String>>,
+    // Only interfaces a default route goes out of
+    pub default_route: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl DiscoveryConfig {
+    // Fill every unset field from `fallback`
+    fn or(&self, fallback: &DiscoveryConfig) -> DiscoveryConfig {
+        DiscoveryConfig {
+            enabled: self.enabled.or(fallback.enabled),
+            include: self.include.clone().or_else(|| fallback.include.clone()),
+            exclude: self.exclude.clone().or_else(|| fallback.exclude.clone()),
+            types: self.types.clone().or_else(|| fallback.types.clone()),
+            default_route: self.default_route.or(fallback.default_route),
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+// `[statistics]`: rolling window over the probes of each interface/server pair
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatisticsConfig {
+    // Seconds of samples kept per pair
+    pub window: Option<f64>,
+    // Weight of the newest sample in the EWMA, 0 < alpha <= 1
+    pub ewma_alpha: Option<f64>,
+    // Samples further than this many scaled MADs from the median are outliers, 0 keeps all
+    pub outlier_mad: Option<f64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InterfaceConfig {
+    pub name: String,
+    // Servers probed on this interface, all `[[servers]]` when unset
+    pub servers: Option<Vec<String>>,
+    // SO_BINDTODEVICE to the interface, true when unset
+    pub bind_device: Option<bool>,
+    // SO_MARK set on probe sockets so `ip rule fwmark` policy routing applies
+    pub fwmark: Option<u32>,
+    // Local address probe sockets are bound to
+    pub source: Option<IpAddr>,
+    // Network namespace the interface lives in, a name under /run/netns or a path
+    pub netns: Option<String>,
+    // VRF device the interface is enslaved to; sockets are bound to it instead
+    pub vrf: Option<String>,
+    // Logical WAN name, same as an entry in `[wan_aliases]`
+    pub wan: Option<String>,
+    // How the servers of this interface are combined into its bandwidth
+    pub aggregate: Option<Aggregate>,
+    #[serde(flatten)]
+    pub settings: ProbeSettings,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl InterfaceConfig {
+    // Name used in metric labels, the bar output and -i; carries the namespace
+    // because the same device name can exist in several namespaces
+    pub fn label(&self) -> String {
+        match &self.netns {
+            Some(netns) => format!("{}/{}", netns, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    fn route(&self) -> Result<Route, String> {
+        let route = Route {
+            bind_device: self.bind_device.unwrap_or(true),
+            fwmark: self.fwmark,
+            source: self.source,
+            netns: self.netns.as_deref().map(netns_path),
+            vrf: self.vrf.clone(),
+        };
+        if route.vrf.is_some() && !route.bind_device {
+            return Err(format!(
+                "Interface {} sets vrf together with bind_device = false",
+                self.label()
+            ));
+        }
+        if !route.bind_device
+            && route.fwmark.is_none()
+            && route.source.is_none()
+            && route.netns.is_none()
+        {
+            return Err(format!(
+                "Interface {} sets bind_device = false without fwmark, source or netns, \
+                 so there is no way to select its route",
+                self.label()
+            ));
+        }
+        Ok(route)
+    }
+}
+
+// `ip netns add` names live under /run/netns, anything with a slash is a path
+fn netns_path(netns: &str) -> PathBuf {
+    if netns.contains('/') {
+        PathBuf::from(netns)
+    } else {
+        Path::new("/run/netns").join(netns)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub address: String,
+    #[serde(flatten)]
+    pub settings: ProbeSettings,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+// Contents of the TOML configuration file
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub max_concurrency: Option<usize>,
+    // Where correction factors are persisted
+    pub state_file: Option<PathBuf>,
+    // Seconds between two readings of the interface counters, 0 disables them
+    pub counter_interval: Option<f64>,
+    // Seconds the interface rates are averaged over
+    pub counter_window: Option<f64>,
+    // Logical WAN name -> interface, e.g. wan0 = "eth0"
+    pub wan_aliases: Option<HashMap<String, String>>,
+    pub discovery: DiscoveryConfig,
+    pub statistics: StatisticsConfig,
+    pub aggregate: Option<Aggregate>,
+    #[serde(flatten)]
+    pub defaults: ProbeSettings,
+    pub interfaces: Vec<InterfaceConfig>,
+    pub servers: Vec<ServerConfig>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+// Values given on the command line, applied on top of the file
+#[derive(Debug, Default, Clone)]
+pub struct CliOverrides {
+    pub interfaces: Vec<String>,
+    pub servers: Vec<String>,
+    pub settings: ProbeSettings,
+    // `-u` URLs given per server
+    pub server_urls: HashMap<String, String>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub api_listen: Option<SocketAddr>,
+    pub max_concurrency: Option<usize>,
+    pub state_file: Option<PathBuf>,
+    pub counter_interval: Option<f64>,
+    pub stats_window: Option<f64>,
+    pub aggregate: Option<Aggregate>,
+    // `--wan <WAN>=<INTERFACE>`, replacing the aliases of the file for those interfaces
+    pub wan_aliases: Vec<(String, String)>,
+    // `--auto*` options
+    pub discovery: DiscoveryConfig,
+    // Interfaces selected by the last discovery scan, used in auto mode
+    pub discovered: Vec<String>,
+}
+
+// Fully resolved probe plan for one interface/server pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    // Label of the interface, `netns/name` for interfaces in another namespace
+    pub interface: String,
+    // Device name inside its namespace
+    pub device: String,
+    pub server: String,
+    pub probe: ProbeMode,
+    pub url: Option<Url>,
+    pub interval: Duration,
+    pub probe_timeout: Duration,
+    pub efficiency: f64,
+    pub model: Model,
+    pub statistics: StatsSettings,
+    // Same for all servers of an interface
+    pub aggregate: Aggregate,
+    pub priority: f64,
+    pub options: ProbeOptions,
+}
+
+// Effective settings after merging built-in defaults, the config file and the CLI
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub metrics_listen: SocketAddr,
+    pub api_listen: SocketAddr,
+    pub max_concurrency: usize,
+    pub state_file: PathBuf,
+    // Interval of the bar output and the per-interface average
+    pub report_interval: Duration,
+    // Interface counter sampling, None when disabled
+    pub counter_interval: Option<Duration>,
+    pub counter_window: Duration,
+    pub wan_aliases: WanAliases,
+    pub statistics: StatsSettings,
+    // Set in auto mode; `targets` then covers the discovered interfaces
+    pub discovery: Option<Discovery>,
+    pub targets: Vec<Target>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    // Merge with the CLI and validate everything that can be checked without probing
+    pub fn resolve(&self, cli: &CliOverrides) -> Result<Settings, String> {
+        self.check_unknown_keys()?;
+
+        let file_defaults = self.defaults.or(&ProbeSettings::builtin());
+        let global = cli.settings.or(&file_defaults);
+
+        // -i replaces both the list of the file and discovery
+        let discovery = self.resolve_discovery(cli);
+        let statistics = self.resolve_statistics(cli)?;
+        let interfaces: Vec<String> = if !cli.interfaces.is_empty() {
+            cli.interfaces.clone()
+        } else if discovery.is_some() {
+            cli.discovered.clone()
+        } else {
+            self.interfaces.iter().map(|i| i.label()).collect()
+        };
+        if interfaces.is_empty() && discovery.is_none() {
+            return Err(
+                "No interfaces specified. Use -i/--interface, --auto or [[interfaces]] in the config file."
+                    .to_string(),
+            );
+        }
+
+        let mut targets = Vec::new();
+        for (index, interface) in interfaces.iter().enumerate() {
+            if interfaces[..index].contains(interface) {
+                return Err(format!("Interface {} is listed twice", interface));
+            }
+
+            let interface_config = self.interfaces.iter().find(|i| &i.label() == interface);
+            let aggregate = cli
+                .aggregate
+                .or(interface_config.and_then(|i| i.aggregate))
+                .or(self.aggregate)
+                .unwrap_or(Aggregate::Mean);
+            let (device, route) = match interface_config {
+                Some(interface_config) => {
+                    (interface_config.name.as_str(), interface_config.route()?)
+                }
+                None => (interface.as_str(), Route::default()),
+            };
+            let servers: Vec<String> = if !cli.servers.is_empty() {
+                cli.servers.clone()
+            } else if let Some(servers) = interface_config.and_then(|i| i.servers.clone()) {
+                servers
+            } else {
+                self.servers.iter().map(|s| s.address.clone()).collect()
+            };
+            if servers.is_empty() {
+                return Err(format!(
+                    "No servers specified for {}. Use -s/--server or [[servers]] in the config file.",
+                    interface
+                ));
+            }
+
+            for server in servers {
+                let mut settings = cli.settings.clone();
+                if let Some(url) = cli.server_urls.get(&server) {
+                    settings.url = Some(url.clone());
+                }
+                if let Some(server_config) = self.servers.iter().find(|s| s.address == server) {
+                    settings = settings.or(&server_config.settings);
+                }
+                if let Some(interface_config) = interface_config {
+                    settings = settings.or(&interface_config.settings);
+                }
+                let settings = settings.or(&global);
+
+                targets.push(build_target(
+                    interface, device, &server, &settings, &route, statistics, aggregate,
+                )?);
+            }
+        }
+
+        let report_interval = positive(global.interval, "interval")?;
+        let max_concurrency = cli.max_concurrency.or(self.max_concurrency).unwrap_or(4);
+        if max_concurrency == 0 {
+            return Err("max_concurrency must be at least 1".to_string());
+        }
+        let counter_interval = match cli.counter_interval.or(self.counter_interval) {
+            Some(0.0) => None,
+            secs => Some(positive(secs.or(Some(0.25)), "counter_interval")?),
+        };
+        let counter_window = positive(self.counter_window.or(Some(1.0)), "counter_window")?;
+        if counter_interval.is_some_and(|interval| interval > counter_window) {
+            return Err("counter_window must not be shorter than counter_interval".to_string());
+        }
+
+        Ok(Settings {
+            metrics_listen: cli
+                .metrics_listen
+                .or(self.listen.metrics)
+                .unwrap_or_else(|| DEFAULT_METRICS_LISTEN.parse().unwrap()),
+            api_listen: cli
+                .api_listen
+                .or(self.listen.api)
+                .unwrap_or_else(|| DEFAULT_API_LISTEN.parse().unwrap()),
+            max_concurrency,
+            state_file: cli
+                .state_file
+                .clone()
+                .or_else(|| self.state_file.clone())
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE)),
+            report_interval,
+            counter_interval,
+            counter_window,
+            wan_aliases: self.resolve_wan_aliases(cli)?,
+            statistics,
+            discovery,
+            targets,
+        })
+    }
+
+    // `[wan_aliases]` and per-interface `wan`, then the CLI; built-in wan0/wan1 only when
+    // no alias is configured anywhere
+    fn resolve_wan_aliases(&self, cli: &CliOverrides) -> Result<WanAliases, String> {
+        let mut pairs: Vec<(String, String)> = self
+            .wan_aliases
+            .iter()
+            .flatten()
+            .map(|(wan, interface)| (wan.clone(), interface.clone()))
+            .collect();
+        pairs.sort();
+        for interface in &self.interfaces {
+            if let Some(wan) = &interface.wan {
+                pairs.push((wan.clone(), interface.label()));
+            }
+        }
+        if pairs.is_empty() && cli.wan_aliases.is_empty() {
+            return Ok(WanAliases::builtin());
+        }
+
+        for (wan, interface) in &cli.wan_aliases {
+            pairs.retain(|(w, i)| w != wan && i != interface);
+            pairs.push((wan.clone(), interface.clone()));
+        }
+        WanAliases::new(pairs)
+    }
+
+    fn resolve_statistics(&self, cli: &CliOverrides) -> Result<StatsSettings, String> {
+        let statistics = &self.statistics;
+        let window = positive(
+            cli.stats_window.or(statistics.window).or(Some(60.0)),
+            "statistics.window",
+        )?;
+        let ewma_alpha = statistics.ewma_alpha.unwrap_or(0.3);
+        if !(ewma_alpha > 0.0 && ewma_alpha <= 1.0) {
+            return Err(format!(
+                "statistics.ewma_alpha must be in (0, 1], got {}",
+                ewma_alpha
+            ));
+        }
+        let outlier_mad = match statistics.outlier_mad.unwrap_or(3.5) {
+            0.0 => None,
+            mad if mad > 0.0 && mad.is_finite() => Some(mad),
+            mad => {
+                return Err(format!(
+                    "statistics.outlier_mad must be 0 or a positive number, got {}",
+                    mad
+                ))
+            }
+        };
+        Ok(StatsSettings {
+            window,
+            ewma_alpha,
+            outlier_mad,
+        })
+    }
+
+    fn resolve_discovery(&self, cli: &CliOverrides) -> Option<Discovery> {
+        let discovery = cli.discovery.or(&self.discovery);
+        if !cli.interfaces.is_empty() || discovery.enabled != Some(true) {
+            return None;
+        }
+        Some(Discovery {
+            include: discovery.include.unwrap_or_default(),
+            exclude: discovery.exclude.unwrap_or_default(),
+            types: discovery.types.unwrap_or_default(),
+            default_route: discovery.default_route.unwrap_or(false),
+        })
+    }
+
+    fn check_unknown_keys(&self) -> Result<(), String> {
+        let mut unknown: Vec<String> = self.unknown.keys().cloned().collect();
+        unknown.extend(
+            self.discovery
+                .unknown
+                .keys()
+                .map(|k| format!("discovery.{}", k)),
+        );
+        unknown.extend(
+            self.statistics
+                .unknown
+                .keys()
+                .map(|k| format!("statistics.{}", k)),
+        );
+        for interface in &self.interfaces {
+            unknown.extend(
+                interface
+                    .unknown
+                    .keys()
+                    .map(|k| format!("interfaces[{}].{}", interface.name, k)),
+            );
+        }
+        for server in &self.servers {
+            unknown.extend(
+                server
+                    .unknown
+                    .keys()
+                    .map(|k| format!("servers[{}].{}", server.address, k)),
+            );
+        }
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unknown config keys: {}", unknown.join(", ")))
+        }
+    }
+}
+
+fn positive(value: Option<f64>, name: &str) -> Result<Duration, String> {
+    match value {
+        Some(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        Some(secs) => Err(format!(
+            "{} must be a positive number of seconds, got {}",
+            name, secs
+        )),
+        None => Err(format!("{} is not set", name)),
+    }
+}
+
+// Turn fully merged settings into a probe plan; every field is set at this point
+fn build_target(
+    interface: &str,
+    device: &str,
+    server: &str,
+    settings: &ProbeSettings,
+    route: &Route,
+    statistics: StatsSettings,
+    aggregate: Aggregate,
+) -> Result<Target, String> {
+    let context = |e: String| format!("{} -> {}: {}", interface, server, e);
+
+    let probe = settings.probe.unwrap_or(ProbeMode::Estimate);
+    let url = match &settings.url {
+        Some(url) => {
+            Some(Url::parse(url).map_err(|e| context(format!("invalid URL '{}': {}", url, e)))?)
+        }
+        None => None,
+    };
+    if probe == ProbeMode::Http && url.is_none() {
+        return Err(context(
+            "the HTTP probe needs a URL (-u/--url or `url` in the config file)".to_string(),
+        ));
+    }
+
+    let model = settings.model.unwrap_or(Model::default_for(probe));
+    if model == Model::Goodput && probe == ProbeMode::Estimate {
+        return Err(context(
+            "the goodput model needs a probe that transfers data (bulk, http, reflector or responsiveness)"
+                .to_string(),
+        ));
+    }
+
+    let efficiency = settings.efficiency.unwrap_or(0.75);
+    if !(efficiency > 0.0 && efficiency.is_finite()) {
+        return Err(context(format!(
+            "efficiency must be positive, got {}",
+            efficiency
+        )));
+    }
+
+    let priority = settings.priority.unwrap_or(1.0);
+    if !(priority > 0.0 && priority.is_finite()) {
+        return Err(context(format!(
+            "priority must be positive, got {}",
+            priority
+        )));
+    }
+
+    let flows = settings.flows.unwrap_or(4);
+    if flows == 0 {
+        return Err(context("flows must be at least 1".to_string()));
+    }
+
+    Ok(Target {
+        interface: interface.to_string(),
+        device: device.to_string(),
+        server: server.to_string(),
+        probe,
+        url,
+        interval: positive(settings.interval, "interval").map_err(context)?,
+        probe_timeout: positive(settings.probe_timeout, "probe_timeout").map_err(context)?,
+        efficiency,
+        model,
+        statistics,
+        aggregate,
+        priority,
+        options: ProbeOptions {
+            connect_timeout: positive(settings.connect_timeout, "connect_timeout")
+                .map_err(context)?,
+            bytes: settings.bytes.unwrap_or(10_000_000),
+            duration: positive(settings.duration, "duration").map_err(context)?,
+            request: settings
+                .request
+                .as_ref()
+                .map(|r| r.replace("\\r", "\r").replace("\\n", "\n").into_bytes()),
+            strict_bind: settings.strict_bind.unwrap_or(false),
+            flows,
+            route: route.clone(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(text: &str, cli: &CliOverrides) -> Result<Settings, String> {
+        toml::from_str::<Config>(text).unwrap().resolve(cli)
+    }
+
+    fn interval(settings: &Settings, interface: &str, server: &str) -> f64 {
+        settings
+            .targets
+            .iter()
+            .find(|t| t.interface == interface && t.server == server)
+            .unwrap()
+            .interval
+            .as_secs_f64()
+    }
+
+    const LAYERED: &str = r#"
+        interval = 5.0
+
+        [[interfaces]]
+        name = "eth0"
+        interval = 4.0
+
+        [[interfaces]]
+        name = "eth1"
+
+        [[servers]]
+        address = "192.0.2.1"
+        interval = 3.0
+
+        [[servers]]
+        address = "192.0.2.2"
+    "#;
+
+    #[test]
+    fn most_specific_setting_wins() {
+        let settings = resolve(LAYERED, &CliOverrides::default()).unwrap();
+
+        assert_eq!(interval(&settings, "eth0", "192.0.2.1"), 3.0);
+        assert_eq!(interval(&settings, "eth0", "192.0.2.2"), 4.0);
+        assert_eq!(interval(&settings, "eth1", "192.0.2.1"), 3.0);
+        assert_eq!(interval(&settings, "eth1", "192.0.2.2"), 5.0);
+        assert_eq!(settings.report_interval, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn command_line_overrides_every_layer() {
+        let cli = CliOverrides {
+            settings: ProbeSettings {
+                interval: Some(2.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let settings = resolve(LAYERED, &cli).unwrap();
+
+        assert_eq!(settings.targets.len(), 4);
+        assert!(settings
+            .targets
+            .iter()
+            .all(|t| t.interval == Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn command_line_lists_replace_the_file() {
+        let cli = CliOverrides {
+            interfaces: vec!["eth0".to_string()],
+            servers: vec!["198.51.100.1".to_string()],
+            ..Default::default()
+        };
+        let settings = resolve(LAYERED, &cli).unwrap();
+
+        assert_eq!(settings.targets.len(), 1);
+        // The interface section still applies to the interface given with -i
+        assert_eq!(interval(&settings, "eth0", "198.51.100.1"), 4.0);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let cli = CliOverrides::default();
+        let with = |extra: &str| {
+            resolve(
+                &format!(
+                    "{}\n[[interfaces]]\nname = \"eth0\"\n[[servers]]\naddress = \"192.0.2.1\"",
+                    extra
+                ),
+                &cli,
+            )
+        };
+
+        assert!(with("").is_ok());
+        for extra in [
+            "efficiency = 0.0",
+            "priority = -1.0",
+            "flows = 0",
+            "interval = 0.0",
+            "max_concurrency = 0",
+            "probe = \"http\"",
+            "model = \"goodput\"",
+            "url = \"not a url\"",
+            "typo = 1",
+            "[statistics]\newma_alpha = 1.5",
+        ] {
+            assert!(with(extra).is_err(), "accepted {}", extra);
+        }
+        assert!(with("probe = \"bulk\"\nmodel = \"goodput\"").is_ok());
+    }
+
+    #[test]
+    fn duplicate_interfaces_are_rejected() {
+        let cli = CliOverrides {
+            interfaces: vec!["eth0".to_string(), "eth0".to_string()],
+            servers: vec!["192.0.2.1".to_string()],
+            ..Default::default()
+        };
+
+        assert!(Config::default().resolve(&cli).is_err());
+    }
+}
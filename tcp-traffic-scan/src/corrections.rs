@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_STATE_FILE: &str = "corrections.json";
+
+// One correction factor and where it came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correction {
+    pub factor: f64,
+    // Unix time of the last change, 0 for the built-in default
+    pub updated_at: u64,
+    // Who made the change, e.g. "/tcpflow from 192.0.2.10"
+    pub source: String,
+}
+
+impl Correction {
+    pub fn new(factor: f64, source: String) -> Self {
+        Correction {
+            factor,
+            updated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            source,
+        }
+    }
+}
+
+// Correction factors as kept in memory and in the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Corrections {
+    // Applied to interfaces without their own factor
+    pub default: Correction,
+    // Keyed by the actual interface name (e.g. "eth0"), not the WAN alias
+    pub interfaces: BTreeMap<String, Correction>,
+}
+
+impl Default for Corrections {
+    fn default() -> Self {
+        Corrections {
+            default: Correction {
+                factor: 1.0,
+                updated_at: 0,
+                source: "built-in".to_string(),
+            },
+            interfaces: BTreeMap::new(),
+        }
+    }
+}
+
+// State file as written, with each correction checked on its own
+#[derive(Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    default: Option<serde_json::Value>,
+    #[serde(default)]
+    interfaces: BTreeMap<String, serde_json::Value>,
+}
+
+fn parse_correction(value: serde_json::Value) -> Option<Correction> {
+    let correction: Correction = serde_json::from_value(value).ok()?;
+    (correction.factor > 0.0 && correction.factor.is_finite()).then_some(correction)
+}
+
+impl Corrections {
+    pub fn factor(&self, interface: &str) -> f64 {
+        self.interfaces
+            .get(interface)
+            .unwrap_or(&self.default)
+            .factor
+    }
+
+    // Read the state file; Ok(None) when it does not exist yet. An entry that is not a valid
+    // correction (e.g. a null factor) is skipped with a warning instead of losing the others.
+    pub fn load(path: &Path) -> io::Result<Option<Corrections>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let file: StateFile = serde_json::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut corrections = Corrections::default();
+        if let Some(value) = file.default {
+            match parse_correction(value) {
+                Some(correction) => corrections.default = correction,
+                None => eprintln!(
+                    "Ignoring the invalid default correction in {}",
+                    path.display()
+                ),
+            }
+        }
+        for (interface, value) in file.interfaces {
+            match parse_correction(value) {
+                Some(correction) => {
+                    corrections.interfaces.insert(interface, correction);
+                }
+                None => eprintln!(
+                    "Ignoring the invalid correction for {} in {}",
+                    interface,
+                    path.display()
+                ),
+            }
+        }
+        Ok(Some(corrections))
+    }
+
+    // Write to a temporary file next to `path` and rename it over the old state,
+    // so a crash never leaves a truncated file behind. The directory is synced too,
+    // otherwise the rename itself can be lost in a crash.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut file = fs::File::create(&tmp)?;
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_skips_invalid_entries() {
+        let path = std::env::temp_dir().join(format!("corrections-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{
+                "default": {"factor": null, "updated_at": 1, "source": "/tcpflow"},
+                "interfaces": {
+                    "eth0": {"factor": 0.8, "updated_at": 1, "source": "/tcpflow"},
+                    "eth1": {"factor": -2.0, "updated_at": 1, "source": "/tcpflow"}
+                }
+            }"#,
+        )
+        .unwrap();
+        let corrections = Corrections::load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(corrections.default.factor, 1.0);
+        assert_eq!(corrections.factor("eth0"), 0.8);
+        assert!(!corrections.interfaces.contains_key("eth1"));
+    }
+}
@@ -0,0 +1,206 @@
+// Passive utilization from the kernel interface counters in sysfs. Sampled much more often
+// than the active probes run; rates are averaged over a sliding window so a single late
+// sample does not show up as a spike.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use crate::{
+    remove_series, INTERFACE_BPS_GAUGE, INTERFACE_DROPPED_COUNTER, INTERFACE_ERRORS_COUNTER,
+    INTERFACE_PPS_GAUGE,
+};
+
+// What to sample and how often
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    // None disables sampling
+    pub interval: Option<Duration>,
+    pub window: Duration,
+    // Interface label -> device name in /sys/class/net
+    pub interfaces: BTreeMap<String, String>,
+}
+
+// One reading of /sys/class/net/<device>/statistics
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_packets: u64,
+    tx_packets: u64,
+    rx_dropped: u64,
+    tx_dropped: u64,
+    rx_errors: u64,
+    tx_errors: u64,
+}
+
+impl Stats {
+    fn read(device: &str) -> io::Result<Stats> {
+        let dir = format!("/sys/class/net/{}/statistics", device);
+        let counter = |name: &str| -> io::Result<u64> {
+            fs::read_to_string(format!("{}/{}", dir, name))?
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        Ok(Stats {
+            rx_bytes: counter("rx_bytes")?,
+            tx_bytes: counter("tx_bytes")?,
+            rx_packets: counter("rx_packets")?,
+            tx_packets: counter("tx_packets")?,
+            rx_dropped: counter("rx_dropped")?,
+            tx_dropped: counter("tx_dropped")?,
+            rx_errors: counter("rx_errors")?,
+            tx_errors: counter("tx_errors")?,
+        })
+    }
+
+    // Counters go backwards when the device is recreated (PPP redial, USB modem replug)
+    fn reset_since(&self, earlier: &Stats) -> bool {
+        self.rx_bytes < earlier.rx_bytes
+            || self.tx_bytes < earlier.tx_bytes
+            || self.rx_packets < earlier.rx_packets
+            || self.tx_packets < earlier.tx_packets
+            || self.rx_dropped < earlier.rx_dropped
+            || self.tx_dropped < earlier.tx_dropped
+            || self.rx_errors < earlier.rx_errors
+            || self.tx_errors < earlier.tx_errors
+    }
+}
+
+pub struct Sampler {
+    plan: Mutex<Plan>,
+    // Readings within the window, oldest first
+    history: Mutex<HashMap<String, VecDeque<(Instant, Stats)>>>,
+}
+
+impl Sampler {
+    pub fn new(plan: Plan) -> Arc<Sampler> {
+        Arc::new(Sampler {
+            plan: Mutex::new(plan),
+            history: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Switch to a new plan; interfaces no longer sampled stop exporting
+    pub fn update(&self, plan: Plan) {
+        let mut current = self.plan.lock().unwrap();
+        let mut history = self.history.lock().unwrap();
+        for interface in current.interfaces.keys() {
+            if plan.interval.is_none() || !plan.interfaces.contains_key(interface) {
+                history.remove(interface);
+                remove_interface_series(interface);
+            }
+        }
+        *current = plan;
+    }
+
+    // Sample until `running` is cleared
+    pub async fn run(self: Arc<Self>, running: Arc<AtomicBool>) {
+        while running.load(Ordering::SeqCst) {
+            let interval = self.plan.lock().unwrap().interval;
+            match interval {
+                Some(interval) => {
+                    self.sample();
+                    tokio::time::sleep(interval).await;
+                }
+                // Disabled; check again for a reload that turns sampling on
+                None => tokio::time::sleep(Duration::from_secs(1)).await,
+            }
+        }
+    }
+
+    fn sample(&self) {
+        let plan = self.plan.lock().unwrap().clone();
+        let mut history = self.history.lock().unwrap();
+        let now = Instant::now();
+
+        for (interface, device) in &plan.interfaces {
+            let stats = match Stats::read(device) {
+                Ok(stats) => stats,
+                // The device is gone; stay silent until it is back
+                Err(_) => {
+                    if history.remove(interface).is_some() {
+                        remove_interface_series(interface);
+                    }
+                    continue;
+                }
+            };
+
+            let samples = history.entry(interface.clone()).or_default();
+            match samples.back() {
+                Some((_, last)) if stats.reset_since(last) => samples.clear(),
+                Some((_, last)) => {
+                    let count = |direction: &str, counter: &prometheus::IntCounterVec, d: u64| {
+                        counter.with_label_values(&[interface, direction]).inc_by(d);
+                    };
+                    count(
+                        "rx",
+                        &INTERFACE_DROPPED_COUNTER,
+                        stats.rx_dropped - last.rx_dropped,
+                    );
+                    count(
+                        "tx",
+                        &INTERFACE_DROPPED_COUNTER,
+                        stats.tx_dropped - last.tx_dropped,
+                    );
+                    count(
+                        "rx",
+                        &INTERFACE_ERRORS_COUNTER,
+                        stats.rx_errors - last.rx_errors,
+                    );
+                    count(
+                        "tx",
+                        &INTERFACE_ERRORS_COUNTER,
+                        stats.tx_errors - last.tx_errors,
+                    );
+                }
+                None => {}
+            }
+            samples.push_back((now, stats));
+
+            // Keep the newest sample that is at least a window old as the base of the rates
+            while samples.len() > 1 && now.duration_since(samples[1].0) >= plan.window {
+                samples.pop_front();
+            }
+
+            let (since, base) = samples[0];
+            let elapsed = now.duration_since(since).as_secs_f64();
+            if elapsed <= 0.0 {
+                continue;
+            }
+            let rate = |delta: u64| delta as f64 / elapsed;
+            for (direction, bytes, packets) in [
+                (
+                    "rx",
+                    stats.rx_bytes - base.rx_bytes,
+                    stats.rx_packets - base.rx_packets,
+                ),
+                (
+                    "tx",
+                    stats.tx_bytes - base.tx_bytes,
+                    stats.tx_packets - base.tx_packets,
+                ),
+            ] {
+                INTERFACE_BPS_GAUGE
+                    .with_label_values(&[interface, direction])
+                    .set(rate(bytes) * 8.0);
+                INTERFACE_PPS_GAUGE
+                    .with_label_values(&[interface, direction])
+                    .set(rate(packets));
+            }
+        }
+    }
+}
+
+fn remove_interface_series(interface: &str) {
+    let labels = [("interface", interface)];
+    remove_series(&INTERFACE_BPS_GAUGE, &labels);
+    remove_series(&INTERFACE_PPS_GAUGE, &labels);
+    remove_series(&INTERFACE_DROPPED_COUNTER, &labels);
+    remove_series(&INTERFACE_ERRORS_COUNTER, &labels);
+}
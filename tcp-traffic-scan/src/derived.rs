@@ -0,0 +1,153 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, Opts};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{BANDWIDTH_AVG_GAUGE, BANDWIDTH_GAUGE, CORRECTIONS, INTERFACE_BPS_GAUGE};
+
+// Metrics computed from the raw measurements and the correction factors on every scrape,
+// so they always agree with the raw series they are derived from and disappear with them.
+// The raw gauges must be registered before this collector is gathered.
+pub struct DerivedCollector {
+    // Unpopulated vectors, only used for their descriptors
+    templates: Vec<GaugeVec>,
+}
+
+fn correction_factor_vec() -> GaugeVec {
+    let opts = Opts::new(
+        "correction_factor",
+        "Correction factor currently applied to the interface",
+    )
+    .namespace("tcp_traffic_scan");
+    GaugeVec::new(opts, &["interface"]).unwrap()
+}
+
+fn bandwidth_corrected_vec() -> GaugeVec {
+    let opts = Opts::new(
+        "tcp_bandwidth_corrected_bps",
+        "TCP bandwidth estimation multiplied by the correction factor in bps",
+    )
+    .namespace("tcp_traffic_scan");
+    GaugeVec::new(opts, &["interface", "server_ip"]).unwrap()
+}
+
+fn bandwidth_avg_corrected_vec() -> GaugeVec {
+    let opts = Opts::new(
+        "tcp_bandwidth_avg_corrected_bps",
+        "TCP bandwidth average per interface multiplied by the correction factor in bps",
+    )
+    .namespace("tcp_traffic_scan");
+    GaugeVec::new(opts, &["interface"]).unwrap()
+}
+
+fn headroom_vec() -> GaugeVec {
+    let opts = Opts::new(
+        "headroom_bps",
+        "Corrected average bandwidth estimate minus the traffic on the interface in bps, not below 0",
+    )
+    .namespace("tcp_traffic_scan");
+    GaugeVec::new(opts, &["interface", "direction"]).unwrap()
+}
+
+fn utilization_vec() -> GaugeVec {
+    let opts = Opts::new(
+        "utilization_ratio",
+        "Traffic on the interface divided by the corrected average bandwidth estimate",
+    )
+    .namespace("tcp_traffic_scan");
+    GaugeVec::new(opts, &["interface", "direction"]).unwrap()
+}
+
+impl DerivedCollector {
+    pub fn new() -> Self {
+        DerivedCollector {
+            templates: vec![
+                correction_factor_vec(),
+                bandwidth_corrected_vec(),
+                bandwidth_avg_corrected_vec(),
+                headroom_vec(),
+                utilization_vec(),
+            ],
+        }
+    }
+}
+
+// Current label values and value of every series of `gauge`
+pub fn samples(gauge: &GaugeVec) -> Vec<(HashMap<String, String>, f64)> {
+    let mut samples = Vec::new();
+    for family in gauge.collect() {
+        for metric in family.get_metric() {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                .collect();
+            samples.push((labels, metric.get_gauge().get_value()));
+        }
+    }
+    samples
+}
+
+fn label<'a>(labels: &'a HashMap<String, String>, name: &str) -> &'a str {
+    labels.get(name).map(String::as_str).unwrap_or("")
+}
+
+impl Collector for DerivedCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.templates.iter().flat_map(|t| t.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let corrections = CORRECTIONS.lock().unwrap().clone();
+        let factors = correction_factor_vec();
+        let corrected = bandwidth_corrected_vec();
+        let avg_corrected = bandwidth_avg_corrected_vec();
+        let headroom = headroom_vec();
+        let utilization = utilization_vec();
+
+        // Every measured interface plus those with a factor of their own
+        let mut interfaces: BTreeSet<String> = corrections.interfaces.keys().cloned().collect();
+
+        for (labels, value) in samples(&BANDWIDTH_GAUGE) {
+            let interface = label(&labels, "interface");
+            corrected
+                .with_label_values(&[interface, label(&labels, "server_ip")])
+                .set(value * corrections.factor(interface));
+            interfaces.insert(interface.to_string());
+        }
+        let mut capacities = HashMap::new();
+        for (labels, value) in samples(&BANDWIDTH_AVG_GAUGE) {
+            let interface = label(&labels, "interface");
+            let capacity = value * corrections.factor(interface);
+            avg_corrected.with_label_values(&[interface]).set(capacity);
+            capacities.insert(interface.to_string(), capacity);
+            interfaces.insert(interface.to_string());
+        }
+        // Both sides from the same scrape, so there is no skew between them
+        for (labels, used) in samples(&INTERFACE_BPS_GAUGE) {
+            let interface = label(&labels, "interface");
+            let Some(&capacity) = capacities.get(interface) else {
+                continue;
+            };
+            let series = [interface, label(&labels, "direction")];
+            headroom
+                .with_label_values(&series)
+                .set((capacity - used).max(0.0));
+            if capacity > 0.0 {
+                utilization.with_label_values(&series).set(used / capacity);
+            }
+        }
+        for interface in &interfaces {
+            factors
+                .with_label_values(&[interface])
+                .set(corrections.factor(interface));
+        }
+
+        let mut families = factors.collect();
+        families.extend(corrected.collect());
+        families.extend(avg_corrected.collect());
+        families.extend(headroom.collect());
+        families.extend(utilization.collect());
+        families
+    }
+}
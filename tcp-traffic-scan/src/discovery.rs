@@ -0,0 +1,663 @@
+// Interface discovery over rtnetlink: dump links, addresses and routes, and watch for
+// link/address/route events so interfaces that come and go (PPP, LTE, USB) are probed
+// without restarting.
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+// Bursts of events (a PPP link brings its link, address and route up within milliseconds)
+// are coalesced into one rescan
+const SETTLE: Duration = Duration::from_millis(300);
+
+// One network interface as seen by the kernel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub index: u32,
+    pub name: String,
+    // Hardware type, e.g. ether, ppp, none (raw IP, as used by LTE modems), loopback
+    pub link_type: String,
+    // Driver kind of virtual links, e.g. vlan, veth, wireguard
+    pub kind: Option<String>,
+    // Administratively up and running (carrier)
+    pub up: bool,
+    // Addresses other than link-local ones
+    pub addresses: Vec<IpAddr>,
+    // A default route in any table goes out of this interface
+    pub default_route: bool,
+}
+
+// Which links are probed in auto mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovery {
+    // Name patterns (`*`, `?`); every name when empty
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Link types or kinds; every type except loopback when empty
+    pub types: Vec<String>,
+    // Only links carrying a default route
+    pub default_route: bool,
+}
+
+impl Discovery {
+    // Names of the links to probe, in kernel index order. A link joins once it is up and has
+    // an address; anything else could not carry a probe. A link of `previous` that went down
+    // stays, usually without its address and route, so its probes report it down until it
+    // comes back or is deleted.
+    pub fn select(&self, links: &[Link], previous: &[String]) -> Vec<String> {
+        links
+            .iter()
+            .filter(|link| {
+                if link.up {
+                    !link.addresses.is_empty() && (!self.default_route || link.default_route)
+                } else {
+                    previous.contains(&link.name)
+                }
+            })
+            .filter(|link| {
+                self.include.is_empty() || self.include.iter().any(|p| matches(p, &link.name))
+            })
+            .filter(|link| !self.exclude.iter().any(|p| matches(p, &link.name)))
+            .filter(|link| {
+                if self.types.is_empty() {
+                    link.link_type != "loopback"
+                } else {
+                    self.types
+                        .iter()
+                        .any(|t| *t == link.link_type || Some(t) == link.kind.as_ref())
+                }
+            })
+            .map(|link| link.name.clone())
+            .collect()
+    }
+}
+
+impl fmt::Display for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "*".to_string()
+            } else {
+                items.join(",")
+            }
+        };
+        write!(
+            f,
+            "include={} exclude={} types={}",
+            list(&self.include),
+            self.exclude.join(","),
+            list(&self.types)
+        )?;
+        if self.default_route {
+            write!(f, " default_route")?;
+        }
+        Ok(())
+    }
+}
+
+// Shell-style match of `name` against `pattern` with `*` and `?`
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Call `on_change` with a fresh scan every time the set of links, their state, addresses
+// or default routes differ from `current`. Runs until `on_change` returns false; blocking,
+// so it gets its own thread.
+#[cfg(target_os = "linux")]
+pub fn watch(
+    mut current: Vec<Link>,
+    mut on_change: impl FnMut(Vec<Link>) -> bool,
+) -> io::Result<()> {
+    let groups = (libc::RTMGRP_LINK
+        | libc::RTMGRP_IPV4_IFADDR
+        | libc::RTMGRP_IPV6_IFADDR
+        | libc::RTMGRP_IPV4_ROUTE
+        | libc::RTMGRP_IPV6_ROUTE) as u32;
+    let events = netlink::Socket::open(groups)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        // The content is not needed, every event leads to a full rescan. ENOBUFS means
+        // events were dropped, which a rescan covers as well.
+        match events.recv(&mut buf) {
+            Ok(_) => {}
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        std::thread::sleep(SETTLE);
+        events.drain(&mut buf);
+
+        let links = scan()?;
+        if links != current {
+            current = links.clone();
+            if !on_change(links) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_current: Vec<Link>, _on_change: impl FnMut(Vec<Link>) -> bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Interface discovery is only supported on Linux",
+    ))
+}
+
+// Current links with their addresses and default routes
+#[cfg(target_os = "linux")]
+pub fn scan() -> io::Result<Vec<Link>> {
+    let socket = netlink::Socket::open(0)?;
+
+    let mut links = Vec::new();
+    for payload in socket.dump(libc::RTM_GETLINK, 16)? {
+        if let Some(link) = netlink::parse_link(&payload) {
+            links.push(link);
+        }
+    }
+    for (index, address) in socket
+        .dump(libc::RTM_GETADDR, 8)?
+        .iter()
+        .filter_map(|payload| netlink::parse_address(payload))
+    {
+        if let Some(link) = links.iter_mut().find(|l| l.index == index) {
+            link.addresses.push(address);
+        }
+    }
+    for index in socket
+        .dump(libc::RTM_GETROUTE, 12)?
+        .iter()
+        .flat_map(|payload| netlink::parse_default_route(payload))
+    {
+        if let Some(link) = links.iter_mut().find(|l| l.index == index) {
+            link.default_route = true;
+        }
+    }
+
+    links.sort_by_key(|link| link.index);
+    for link in &mut links {
+        link.addresses.sort();
+        link.addresses.dedup();
+    }
+    Ok(links)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn scan() -> io::Result<Vec<Link>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Interface discovery is only supported on Linux",
+    ))
+}
+
+// Just enough of the rtnetlink wire format for the dumps above
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use super::Link;
+
+    const HEADER_LEN: usize = 16;
+
+    pub struct Socket {
+        fd: OwnedFd,
+    }
+
+    impl Socket {
+        // Netlink route socket subscribed to `groups` (0 for requests only)
+        pub fn open(groups: u32) -> io::Result<Socket> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = Socket {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            };
+
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = groups;
+            let ret = unsafe {
+                libc::bind(
+                    fd,
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(socket)
+        }
+
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        }
+
+        // Discard queued events
+        pub fn drain(&self, buf: &mut [u8]) {
+            loop {
+                let n = unsafe {
+                    libc::recv(
+                        self.fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        libc::MSG_DONTWAIT,
+                    )
+                };
+                let overflow = io::Error::last_os_error().raw_os_error() == Some(libc::ENOBUFS);
+                if n == 0 || (n < 0 && !overflow) {
+                    return;
+                }
+            }
+        }
+
+        // Send a dump request with an all-zero family header of `body_len` bytes and
+        // collect the payloads of the answers
+        pub fn dump(&self, msg_type: u16, body_len: usize) -> io::Result<Vec<Vec<u8>>> {
+            let seq = msg_type as u32;
+            let len = HEADER_LEN + body_len;
+            let mut request = vec![0u8; len];
+            request[0..4].copy_from_slice(&(len as u32).to_ne_bytes());
+            request[4..6].copy_from_slice(&msg_type.to_ne_bytes());
+            let flags = (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16;
+            request[6..8].copy_from_slice(&flags.to_ne_bytes());
+            request[8..12].copy_from_slice(&seq.to_ne_bytes());
+
+            let sent = unsafe {
+                libc::send(
+                    self.fd.as_raw_fd(),
+                    request.as_ptr() as *const libc::c_void,
+                    request.len(),
+                    0,
+                )
+            };
+            if sent < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut payloads = Vec::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = self.recv(&mut buf)?;
+                for (header, payload) in messages(&buf[..n]) {
+                    if header.seq != seq {
+                        continue;
+                    }
+                    match header.msg_type as libc::c_int {
+                        libc::NLMSG_DONE => return Ok(payloads),
+                        libc::NLMSG_ERROR => {
+                            let errno = payload
+                                .get(0..4)
+                                .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+                                .unwrap_or(0);
+                            if errno != 0 {
+                                return Err(io::Error::from_raw_os_error(-errno));
+                            }
+                        }
+                        _ => payloads.push(payload.to_vec()),
+                    }
+                }
+            }
+        }
+    }
+
+    struct Header {
+        msg_type: u16,
+        seq: u32,
+    }
+
+    fn align(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    fn u16_at(buf: &[u8], at: usize) -> Option<u16> {
+        Some(u16::from_ne_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> Option<u32> {
+        Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+    }
+
+    fn messages(mut buf: &[u8]) -> Vec<(Header, &[u8])> {
+        let mut messages = Vec::new();
+        while let (Some(len), Some(msg_type), Some(seq)) =
+            (u32_at(buf, 0), u16_at(buf, 4), u32_at(buf, 8))
+        {
+            let len = len as usize;
+            if len < HEADER_LEN || len > buf.len() {
+                break;
+            }
+            messages.push((Header { msg_type, seq }, &buf[HEADER_LEN..len]));
+            buf = &buf[align(len).min(buf.len())..];
+        }
+        messages
+    }
+
+    // (type, value) of the route attributes in `buf`
+    fn attributes(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+        let mut attributes = Vec::new();
+        while let (Some(len), Some(kind)) = (u16_at(buf, 0), u16_at(buf, 2)) {
+            let len = len as usize;
+            if len < 4 || len > buf.len() {
+                break;
+            }
+            // The top bits flag nested and byte-order attributes
+            attributes.push((kind & 0x3fff, &buf[4..len]));
+            buf = &buf[align(len).min(buf.len())..];
+        }
+        attributes
+    }
+
+    fn string(value: &[u8]) -> String {
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        String::from_utf8_lossy(&value[..end]).into_owned()
+    }
+
+    fn link_type(arphrd: u16) -> String {
+        match arphrd {
+            libc::ARPHRD_ETHER => "ether".to_string(),
+            libc::ARPHRD_PPP => "ppp".to_string(),
+            libc::ARPHRD_NONE => "none".to_string(),
+            // ARPHRD_RAWIP, not in libc
+            519 => "rawip".to_string(),
+            libc::ARPHRD_LOOPBACK => "loopback".to_string(),
+            libc::ARPHRD_TUNNEL => "ipip".to_string(),
+            libc::ARPHRD_TUNNEL6 => "ip6tnl".to_string(),
+            libc::ARPHRD_SIT => "sit".to_string(),
+            libc::ARPHRD_IPGRE => "gre".to_string(),
+            other => format!("arphrd{}", other),
+        }
+    }
+
+    // RTM_NEWLINK: struct ifinfomsg, then attributes
+    pub fn parse_link(payload: &[u8]) -> Option<Link> {
+        let arphrd = u16_at(payload, 2)?;
+        let index = u32_at(payload, 4)?;
+        let flags = u32_at(payload, 8)?;
+
+        let mut name = None;
+        let mut kind = None;
+        for (attribute, value) in attributes(payload.get(16..)?) {
+            match attribute {
+                libc::IFLA_IFNAME => name = Some(string(value)),
+                libc::IFLA_LINKINFO => {
+                    kind = attributes(value)
+                        .into_iter()
+                        .find(|(a, _)| *a == libc::IFLA_INFO_KIND)
+                        .map(|(_, v)| string(v));
+                }
+                _ => {}
+            }
+        }
+
+        let up = libc::IFF_UP as u32 | libc::IFF_RUNNING as u32;
+        Some(Link {
+            index,
+            name: name?,
+            link_type: link_type(arphrd),
+            kind,
+            up: flags & up == up,
+            addresses: Vec::new(),
+            default_route: false,
+        })
+    }
+
+    fn ip(family: u8, value: &[u8]) -> Option<IpAddr> {
+        match family as libc::c_int {
+            libc::AF_INET => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(value).ok()?))),
+            libc::AF_INET6 => Some(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(value).ok()?,
+            ))),
+            _ => None,
+        }
+    }
+
+    // RTM_NEWADDR: struct ifaddrmsg, then attributes. Returns (interface index, address)
+    // for addresses wider than link scope.
+    pub fn parse_address(payload: &[u8]) -> Option<(u32, IpAddr)> {
+        let family = *payload.first()?;
+        let scope = *payload.get(3)?;
+        let index = u32_at(payload, 4)?;
+        if scope >= libc::RT_SCOPE_LINK {
+            return None;
+        }
+
+        // On point-to-point links IFA_ADDRESS is the peer, IFA_LOCAL our own address
+        let attributes = attributes(payload.get(8..)?);
+        let value = attributes
+            .iter()
+            .find(|(a, _)| *a == libc::IFA_LOCAL)
+            .or_else(|| attributes.iter().find(|(a, _)| *a == libc::IFA_ADDRESS))?
+            .1;
+        Some((index, ip(family, value)?))
+    }
+
+    // RTM_NEWROUTE: struct rtmsg, then attributes. Returns the output interfaces of a
+    // unicast default route, several for a multipath route.
+    pub fn parse_default_route(payload: &[u8]) -> Vec<u32> {
+        let (Some(&dst_len), Some(&route_type)) = (payload.get(1), payload.get(7)) else {
+            return Vec::new();
+        };
+        if dst_len != 0 || route_type != libc::RTN_UNICAST {
+            return Vec::new();
+        }
+
+        let mut indexes = Vec::new();
+        for (attribute, value) in attributes(payload.get(12..).unwrap_or_default()) {
+            match attribute {
+                libc::RTA_OIF => indexes.extend(u32_at(value, 0)),
+                libc::RTA_MULTIPATH => {
+                    // struct rtnexthop { len: u16, flags: u8, hops: u8, ifindex: i32 }, ...
+                    let mut rest = value;
+                    while let (Some(len), Some(index)) = (u16_at(rest, 0), u32_at(rest, 4)) {
+                        let len = len as usize;
+                        if len < 8 || len > rest.len() {
+                            break;
+                        }
+                        indexes.push(index);
+                        rest = &rest[align(len).min(rest.len())..];
+                    }
+                }
+                _ => {}
+            }
+        }
+        indexes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_like_the_shell() {
+        assert!(matches("eth*", "eth0"));
+        assert!(matches("eth*", "eth"));
+        assert!(matches("*", ""));
+        assert!(matches("ppp?", "ppp0"));
+        assert!(!matches("ppp?", "ppp10"));
+        assert!(matches("e*0", "eth10"));
+        assert!(!matches("e*0", "eth01"));
+        assert!(matches("*ab", "aab"));
+        assert!(matches("w*g*", "wireguard"));
+        assert!(!matches("wg*", "eth0"));
+        assert!(!matches("", "eth0"));
+    }
+
+    fn link(index: u32, name: &str, up: bool) -> Link {
+        Link {
+            index,
+            name: name.to_string(),
+            link_type: "ether".to_string(),
+            kind: None,
+            up,
+            addresses: if up {
+                vec!["192.0.2.1".parse().unwrap()]
+            } else {
+                Vec::new()
+            },
+            default_route: false,
+        }
+    }
+
+    #[test]
+    fn down_links_stay_only_when_selected_before() {
+        let discovery = Discovery {
+            include: vec!["eth*".to_string()],
+            exclude: vec!["eth9".to_string()],
+            types: Vec::new(),
+            default_route: false,
+        };
+        let links = [
+            link(1, "eth0", true),
+            link(2, "eth1", false),
+            link(3, "eth2", false),
+            link(4, "eth9", true),
+            link(5, "wlan0", true),
+        ];
+
+        assert_eq!(
+            discovery.select(&links, &["eth1".to_string()]),
+            ["eth0", "eth1"]
+        );
+        assert_eq!(discovery.select(&links, &[]), ["eth0"]);
+    }
+
+    // Payloads captured from a veth pair (vp, index 2, 10.18.0.2/24) on x86_64, with the
+    // link attributes other than IFLA_IFNAME and IFLA_LINKINFO left out
+    #[cfg(all(target_os = "linux", target_endian = "little"))]
+    mod captured {
+        use super::super::netlink::{parse_address, parse_default_route, parse_link};
+        use std::net::IpAddr;
+
+        fn bytes(hex: &str) -> Vec<u8> {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        #[test]
+        fn link_with_kind() {
+            let link = parse_link(&bytes(
+                "00000100020000004310010000000000070003007670000010001200090001007665746800000000",
+            ))
+            .unwrap();
+
+            assert_eq!(link.index, 2);
+            assert_eq!(link.name, "vp");
+            assert_eq!(link.link_type, "ether");
+            assert_eq!(link.kind.as_deref(), Some("veth"));
+            assert!(link.up);
+        }
+
+        #[test]
+        fn link_administratively_down() {
+            let link = parse_link(&bytes(
+                "00000100020000000210000000000000070003007670000010001200090001007665746800000000",
+            ))
+            .unwrap();
+
+            assert!(!link.up);
+        }
+
+        #[test]
+        fn loopback_link() {
+            let link =
+                parse_link(&bytes("00000403010000004900010000000000070003006c6f0000")).unwrap();
+
+            assert_eq!(link.name, "lo");
+            assert_eq!(link.link_type, "loopback");
+            assert_eq!(link.kind, None);
+        }
+
+        #[test]
+        fn global_address() {
+            let address = parse_address(&bytes(
+                "0218800002000000080001000a120002080002000a1200020700030076700000080008008000000014000600fffffffffffffffffb4d0500fb4d0500",
+            ));
+
+            assert_eq!(address, Some((2, "10.18.0.2".parse::<IpAddr>().unwrap())));
+        }
+
+        #[test]
+        fn link_local_address_is_skipped() {
+            let address = parse_address(&bytes(
+                "0a4080fd0200000014000100fe8000000000000028cfc6fffe754b9314000600ffffffffffffffff4abb09004abb0900080008008000000005000b0003000000",
+            ));
+
+            assert_eq!(address, None);
+        }
+
+        #[test]
+        fn default_route() {
+            let indexes = parse_default_route(&bytes(
+                "02000000fe0300010000000008000f00fe000000080005000a1200010800040002000000",
+            ));
+
+            assert_eq!(indexes, [2]);
+        }
+
+        #[test]
+        fn multipath_default_route() {
+            let indexes = parse_default_route(&bytes(
+                "02000000640300010000000008000f00640000001c0009001000000002000000080005000a1200010800000001000000",
+            ));
+
+            assert_eq!(indexes, [2, 1]);
+        }
+
+        #[test]
+        fn other_routes_are_ignored() {
+            // 10.18.0.0/24 connected route and the 10.18.0.2 local route
+            for hex in [
+                "02180000fe02fd010000000008000f00fe000000080001000a120000080007000a1200020800040002000000",
+                "02200000ff02fe020000000008000f00ff000000080001000a120002080007000a1200020800040002000000",
+            ] {
+                assert!(parse_default_route(&bytes(hex)).is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use native_tls::{HandshakeError, TlsConnector};
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::probe::{
+    connect_bound, effective_window, receive_bulk, tcp_rtt, Connection, Goodput, Measurement,
+    ProbeOptions,
+};
+use crate::tcp_info::get_tcp_info;
+
+// Timings of one HTTP(S) fetch
+#[derive(Debug, Clone, Copy)]
+pub struct HttpTiming {
+    pub status: u16,
+    // TCP handshake
+    pub connect: Duration,
+    // TLS handshake, None for plain HTTP
+    pub tls_handshake: Option<Duration>,
+    // Request sent -> first response byte
+    pub ttfb: Duration,
+}
+
+// Upper bound on the size of the response header block
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+// Port to connect to for `url` unless the server string already carries one
+pub fn url_port(url: &Url) -> u16 {
+    url.port_or_known_default().unwrap_or(443)
+}
+
+// Fetch `url` from `addr` over a socket bound to `interface`.
+// The transfer stops after `options.bytes`, `options.duration` or the end of the body.
+pub fn measure_http(
+    interface: &str,
+    addr: SocketAddr,
+    url: &Url,
+    options: &ProbeOptions,
+) -> io::Result<Measurement> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no host"))?;
+    let host_header = host_header(url, host);
+
+    let Connection {
+        socket,
+        connect_time,
+        bound,
+    } = connect_bound(interface, addr, options)?;
+    let fd = socket.as_raw_fd();
+    let mut stream: std::net::TcpStream = socket.into();
+    stream.set_read_timeout(Some(options.duration))?;
+    stream.set_write_timeout(Some(options.duration))?;
+
+    let (tls_handshake, response) = match url.scheme() {
+        "http" => (None, fetch(&mut stream, &host_header, url, options)?),
+        "https" => {
+            let connector = TlsConnector::new().map_err(io::Error::other)?;
+            let start = Instant::now();
+            let mut tls = match connector.connect(host, &mut stream) {
+                Ok(tls) => tls,
+                Err(HandshakeError::Failure(e)) => {
+                    return Err(io::Error::other(format!("TLS handshake failed: {}", e)))
+                }
+                Err(HandshakeError::WouldBlock(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "TLS handshake timed out",
+                    ))
+                }
+            };
+            let handshake = start.elapsed();
+            (
+                Some(handshake),
+                fetch(&mut tls, &host_header, url, options)?,
+            )
+        }
+        scheme => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported URL scheme '{}'", scheme),
+            ))
+        }
+    };
+
+    // Snapshot after the transfer while the connection is still open
+    let tcp_info = get_tcp_info(fd);
+    let window_size = effective_window(fd)?;
+
+    Ok(Measurement {
+        rtt: tcp_rtt(&tcp_info).unwrap_or(connect_time / 2),
+        connect_time,
+        bound,
+        window_size,
+        tcp_info,
+        goodput: Some(response.goodput),
+        http: Some(HttpTiming {
+            status: response.status,
+            connect: connect_time,
+            tls_handshake,
+            ttfb: response.ttfb,
+        }),
+        reflector: None,
+        responsiveness: None,
+    })
+}
+
+// Value of the Host header: the port is part of it unless it is the default of the scheme
+fn host_header(url: &Url, host: &str) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+struct Response {
+    status: u16,
+    ttfb: Duration,
+    goodput: Goodput,
+}
+
+// Send a GET for `url` and read the response, timing the first byte and the body transfer
+fn fetch<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    url: &Url,
+    options: &ProbeOptions,
+) -> io::Result<Response> {
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: tcp-traffic-scan/{}\r\nAccept: */*\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let start = Instant::now();
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    // Read until the end of the header block; the first successful read marks TTFB
+    let mut head = Vec::new();
+    let mut buf = [0u8; 16 * 1024];
+    let mut ttfb = None;
+    let header_end = loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before HTTP response headers",
+            ));
+        }
+        ttfb.get_or_insert_with(|| start.elapsed());
+        head.extend_from_slice(&buf[..n]);
+
+        if let Some(pos) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if head.len() > MAX_HEADER_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP response headers too large",
+            ));
+        }
+    };
+
+    let headers = String::from_utf8_lossy(&head[..header_end]);
+    let status = parse_status(&headers)?;
+    if !(200..300).contains(&status) {
+        return Err(io::Error::other(format!("HTTP status {}", status)));
+    }
+
+    let max_bytes = content_length(&headers)
+        .map(|len| len.min(options.bytes))
+        .unwrap_or(options.bytes);
+
+    // Body bytes that arrived together with the headers count towards the transfer
+    let mut body = Cursor::new(head[header_end..].to_vec()).chain(stream);
+    let goodput = receive_bulk(&mut body, max_bytes, options.duration)?;
+
+    Ok(Response {
+        status,
+        ttfb: ttfb.unwrap_or_default(),
+        goodput,
+    })
+}
+
+fn parse_status(headers: &str) -> io::Result<u16> {
+    headers
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP status line"))
+}
+
+fn content_length(headers: &str) -> Option<u64> {
+    headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_header_keeps_a_non_default_port() {
+        let header = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            host_header(&url, url.host_str().unwrap())
+        };
+        assert_eq!(header("http://example.com:8080/x"), "example.com:8080");
+        assert_eq!(header("http://example.com:80/x"), "example.com");
+        assert_eq!(header("https://example.com/x"), "example.com");
+        assert_eq!(header("https://[2001:db8::1]:8443/"), "[2001:db8::1]:8443");
+    }
+
+    #[test]
+    fn status_line() {
+        assert_eq!(
+            parse_status("HTTP/1.1 206 Partial Content\r\n\r\n").unwrap(),
+            206
+        );
+        assert_eq!(parse_status("HTTP/1.0 200\r\n").unwrap(), 200);
+        assert!(parse_status("HTTP/1.1\r\n").is_err());
+        assert!(parse_status("garbage here\r\n").is_err());
+        assert!(parse_status("").is_err());
+    }
+
+    #[test]
+    fn content_length_header() {
+        let headers = "HTTP/1.1 200 OK\r\nServer: x\r\ncontent-LENGTH:  1234 \r\n\r\n";
+        assert_eq!(content_length(headers), Some(1234));
+        assert_eq!(
+            content_length("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            None
+        );
+        assert_eq!(
+            content_length("HTTP/1.1 200 OK\r\nContent-Length: many\r\n\r\n"),
+            None
+        );
+    }
+}
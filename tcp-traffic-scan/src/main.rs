@@ -1,24 +1,69 @@
-use axum::{extract::Query, http::StatusCode, response::IntoResponse, routing::get, Router};
-use clap::Parser;
+mod aggregate;
+mod api;
+mod config;
+mod corrections;
+mod counters;
+mod derived;
+mod discovery;
+mod http_probe;
+mod model;
+mod probe;
+mod reflector;
+mod responsiveness;
+mod scheduler;
+mod stats;
+mod tcp_info;
+mod wan;
+
+use aggregate::Aggregate;
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use clap::{Parser, Subcommand};
+use config::{CliOverrides, Config, DiscoveryConfig, ProbeSettings, Settings, Target};
+use corrections::{Correction, Corrections};
+use counters::Sampler;
+use discovery::Link;
+use http_probe::{measure_http, url_port};
 use lazy_static::lazy_static;
-use libc;
-use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
-use socket2::{Domain, Socket, Type};
+use model::Model;
+use probe::{
+    link_up, measure_goodput, measure_throughput, resolve_server_address, FailureReason,
+    Measurement, ProbeMode,
+};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use reflector::{measure_reflector, ServeOptions};
+use responsiveness::measure_responsiveness;
+use scheduler::{
+    Outcome, OutcomeFn, PairKey, ProbeFn, Publish, Reload, ReloadSummary, ReportFn, Schedule,
+    Scheduler,
+};
+use stats::{Summary, Window};
 use std::collections::HashMap;
-#[cfg(target_os = "linux")]
-use std::ffi::CString;
 use std::io;
-use std::io::Write;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::os::unix::io::AsRawFd;
-#[cfg(not(target_os = "linux"))]
-use std::sync::Once;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex,
 };
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use wan::WanAliases;
+
+// Histogram buckets for WAN latencies, 1ms to 2.5s
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.02, 0.035, 0.05, 0.075, 0.1, 0.15, 0.25, 0.5, 1.0, 2.5,
+];
 
 lazy_static! {
     static ref REGISTRY: Registry = Registry::new();
@@ -39,10 +84,323 @@ lazy_static! {
         REGISTRY.register(Box::new(gauge.clone())).unwrap();
         gauge
     };
-    // Per-interface correction factors: wan0->eth0, wan1->eth1, etc.
-    // Key is the actual interface name (e.g., "eth0"), value is correction factor
-    static ref CORRECTION_FACTORS: Arc<Mutex<HashMap<String, f64>>> = Arc::new(Mutex::new(HashMap::new()));
-    static ref DEFAULT_CORRECTION_FACTOR: Arc<Mutex<f64>> = Arc::new(Mutex::new(1.0));
+    static ref BANDWIDTH_MODEL_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_bandwidth_model_bps",
+            "TCP bandwidth in bps by model (buffer, cwnd, delivery_rate, goodput, mathis, padhye), \
+             for each model the probe has data for",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip", "model"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref LOSS_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_loss_ratio",
+            "Share of its data segments the sender retransmitted or marked lost during the probe, \
+             reflector probes only",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref BANDWIDTH_WINDOW_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_bandwidth_window_bps",
+            "TCP bandwidth over the rolling window without outliers in bps, \
+             by statistic (median, p10, p90, ewma)",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip", "statistic"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref BANDWIDTH_WINDOW_SAMPLES_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_bandwidth_window_samples",
+            "Samples in the rolling bandwidth window, by kind (total, outliers)",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip", "kind"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref BANDWIDTH_AVG_SHARE_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_bandwidth_avg_share",
+            "Share of each server in the bandwidth aggregate of its interface, \
+             0 when it did not contribute",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref GOODPUT_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_goodput_bps",
+            "Measured bulk transfer goodput in bps by phase (ramp_up, steady)",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip", "phase"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref HTTP_TTFB_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "http_ttfb_seconds",
+            "HTTP probe time from request sent to first response byte",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref HTTP_TLS_HANDSHAKE_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "http_tls_handshake_seconds",
+            "HTTPS probe TLS handshake duration",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref HTTP_THROUGHPUT_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "http_throughput_bps",
+            "HTTP probe body transfer throughput in bps",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref REFLECTOR_ECHO_RTT_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "reflector_echo_rtt_seconds",
+            "Median application-level echo round trip against a reflector",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref REFLECTOR_SERVER_SEND_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "reflector_server_send_bps",
+            "Send rate reported by the reflector for the last download in bps",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref RESPONSIVENESS_RTT_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "responsiveness_rtt_seconds",
+            "Median handshake RTT of the responsiveness probe by load (idle, loaded)",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip", "load"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref RESPONSIVENESS_RPM_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "responsiveness_rpm",
+            "Round trips per minute while the responsiveness probe loaded the link",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref RTT_GAUGE: GaugeVec = {
+        let opts = Opts::new("tcp_rtt_seconds", "Smoothed TCP round trip time of the last probe")
+            .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref RTTVAR_GAUGE: GaugeVec = {
+        let opts = Opts::new("tcp_rttvar_seconds", "TCP round trip time variance of the last probe")
+            .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref CONNECT_GAUGE: GaugeVec = {
+        let opts = Opts::new("tcp_connect_seconds", "TCP handshake duration of the last probe")
+            .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref WINDOW_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "tcp_window_bytes",
+            "Effective TCP window used by the estimate in bytes",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref SND_CWND_GAUGE: GaugeVec = {
+        let opts = Opts::new("tcp_snd_cwnd_segments", "TCP congestion window in segments")
+            .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref MSS_GAUGE: GaugeVec = {
+        let opts = Opts::new("tcp_mss_bytes", "TCP sender maximum segment size in bytes")
+            .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref RTT_HISTOGRAM: HistogramVec = {
+        let opts = HistogramOpts::new(
+            "tcp_rtt_distribution_seconds",
+            "Distribution of the smoothed TCP round trip time over all probes",
+        )
+        .namespace("tcp_traffic_scan")
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let histogram = HistogramVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(histogram.clone())).unwrap();
+        histogram
+    };
+    static ref RTTVAR_HISTOGRAM: HistogramVec = {
+        let opts = HistogramOpts::new(
+            "tcp_rttvar_distribution_seconds",
+            "Distribution of the TCP round trip time variance over all probes",
+        )
+        .namespace("tcp_traffic_scan")
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let histogram = HistogramVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(histogram.clone())).unwrap();
+        histogram
+    };
+    static ref CONNECT_HISTOGRAM: HistogramVec = {
+        let opts = HistogramOpts::new(
+            "tcp_connect_distribution_seconds",
+            "Distribution of the TCP handshake duration over all probes",
+        )
+        .namespace("tcp_traffic_scan")
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let histogram = HistogramVec::new(opts, &["interface", "server_ip"]).unwrap();
+        REGISTRY.register(Box::new(histogram.clone())).unwrap();
+        histogram
+    };
+    static ref PROBE_UP_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "probe_up",
+            "1 if the last probe of the interface/server pair succeeded, 0 otherwise",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref PROBE_FAILURES_COUNTER: IntCounterVec = {
+        let opts = Opts::new(
+            "probe_failures_total",
+            "Failed probes of the interface/server pair by reason",
+        )
+        .namespace("tcp_traffic_scan");
+        let counter = IntCounterVec::new(opts, &["interface", "server", "reason"]).unwrap();
+        REGISTRY.register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+    static ref LAST_SUCCESS_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "probe_last_success_timestamp_seconds",
+            "Unix time of the last successful probe of the interface/server pair",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "server"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref INTERFACE_BOUND_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "interface_bound",
+            "1 if the last probe socket was bound to the interface, 0 if it used the default route",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref INTERFACE_UP_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "interface_up",
+            "1 if the interface is up with carrier, 0 if it is down and not probed",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref INTERFACE_BPS_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "interface_bps",
+            "Traffic on the interface from its kernel counters in bps, by direction",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "direction"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref INTERFACE_PPS_GAUGE: GaugeVec = {
+        let opts = Opts::new(
+            "interface_packets_per_second",
+            "Packets per second on the interface from its kernel counters, by direction",
+        )
+        .namespace("tcp_traffic_scan");
+        let gauge = GaugeVec::new(opts, &["interface", "direction"]).unwrap();
+        REGISTRY.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    };
+    static ref INTERFACE_DROPPED_COUNTER: IntCounterVec = {
+        let opts = Opts::new(
+            "interface_dropped_packets_total",
+            "Packets dropped by the interface since the exporter started, by direction",
+        )
+        .namespace("tcp_traffic_scan");
+        let counter = IntCounterVec::new(opts, &["interface", "direction"]).unwrap();
+        REGISTRY.register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+    static ref INTERFACE_ERRORS_COUNTER: IntCounterVec = {
+        let opts = Opts::new(
+            "interface_errors_total",
+            "Receive and transmit errors of the interface since the exporter started, by direction",
+        )
+        .namespace("tcp_traffic_scan");
+        let counter = IntCounterVec::new(opts, &["interface", "direction"]).unwrap();
+        REGISTRY.register(Box::new(counter.clone())).unwrap();
+        counter
+    };
+    // Last link state seen per interface, to log only the transitions
+    static ref LINK_STATES: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
+    // Address each interface/server pair last resolved to; its series carry it as `server_ip`
+    static ref SERVER_IPS: Mutex<HashMap<PairKey, String>> = Mutex::new(HashMap::new());
+    // Recent bandwidth samples of each interface/server pair
+    static ref WINDOWS: Mutex<HashMap<PairKey, Window>> = Mutex::new(HashMap::new());
+    // Default and per-interface correction factors: wan0->eth0, wan1->eth1, etc.
+    static ref CORRECTIONS: Arc<Mutex<Corrections>> = Arc::new(Mutex::new(Corrections::default()));
+    // State file the correction factors are saved to on every change
+    static ref STATE_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+    // Held while a change is applied and saved, so saves reach the file in the order of changes
+    static ref SAVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+    // Logical WAN name -> interface, from `wan_aliases` in the config file
+    static ref WAN_ALIASES: Arc<Mutex<WanAliases>> =
+        Arc::new(Mutex::new(WanAliases::builtin()));
 }
 
 #[derive(serde::Deserialize)]
@@ -51,85 +409,348 @@ struct CorrectionQuery {
     nic: Option<String>,
 }
 
-// Map wan names to actual interface names
+// Map wan names to actual interface names; interface names are used as-is
 fn map_wan_to_interface(wan_name: &str) -> String {
-    match wan_name {
-        "wan0" => "eth0".to_string(),
-        "wan1" => "eth1".to_string(),
-        _ => wan_name.to_string(), // If not wan0/wan1, use as-is
+    WAN_ALIASES.lock().unwrap().interface(wan_name)
+}
+
+// Apply a change to the correction factors and persist the result. The file is written on
+// the blocking pool with no lock held that the handlers or the metrics need.
+async fn update_corrections(change: impl FnOnce(&mut Corrections)) -> io::Result<()> {
+    let _saving = SAVING.lock().await;
+    let corrections = {
+        let mut corrections = CORRECTIONS.lock().unwrap();
+        change(&mut corrections);
+        corrections.clone()
+    };
+    let Some(path) = STATE_FILE.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    tokio::task::spawn_blocking(move || corrections.save(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+// Load persisted correction factors at startup; later changes are written back to `path`
+fn load_corrections(path: PathBuf) {
+    match Corrections::load(&path) {
+        Ok(Some(corrections)) => {
+            println!(
+                "Loaded correction factors from {} (default {}, {} interface(s))",
+                path.display(),
+                corrections.default.factor,
+                corrections.interfaces.len()
+            );
+            *CORRECTIONS.lock().unwrap() = corrections;
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!(
+            "Failed to load correction factors from {}, starting with defaults: {}",
+            path.display(),
+            e
+        ),
     }
+    *STATE_FILE.lock().unwrap() = Some(path);
 }
 
-// Prometheus metrics server on port 59121
-async fn start_metrics_server(running: Arc<AtomicBool>) {
-    let app = Router::new().route("/metrics", get(metrics_handler));
+// Per-pair series, dropped when a reload removes the pair
+fn target_gauges() -> [&'static GaugeVec; 19] {
+    [
+        &BANDWIDTH_GAUGE,
+        &BANDWIDTH_MODEL_GAUGE,
+        &LOSS_GAUGE,
+        &BANDWIDTH_WINDOW_GAUGE,
+        &BANDWIDTH_WINDOW_SAMPLES_GAUGE,
+        &RTT_GAUGE,
+        &RTTVAR_GAUGE,
+        &CONNECT_GAUGE,
+        &WINDOW_GAUGE,
+        &SND_CWND_GAUGE,
+        &MSS_GAUGE,
+        &GOODPUT_GAUGE,
+        &HTTP_TTFB_GAUGE,
+        &HTTP_TLS_HANDSHAKE_GAUGE,
+        &HTTP_THROUGHPUT_GAUGE,
+        &REFLECTOR_ECHO_RTT_GAUGE,
+        &REFLECTOR_SERVER_SEND_GAUGE,
+        &RESPONSIVENESS_RTT_GAUGE,
+        &RESPONSIVENESS_RPM_GAUGE,
+    ]
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:59121")
-        .await
-        .unwrap();
+fn target_histograms() -> [&'static HistogramVec; 3] {
+    [&RTT_HISTOGRAM, &RTTVAR_HISTOGRAM, &CONNECT_HISTOGRAM]
+}
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            while running.load(Ordering::SeqCst) {
-                tokio::time::sleep(Duration::from_millis(100)).await;
+// Drop every series of `vec` carrying all of the given label values
+fn remove_series<T: MetricVecBuilder>(vec: &MetricVec<T>, labels: &[(&str, &str)]) {
+    for family in vec.collect() {
+        for metric in family.get_metric() {
+            let values: HashMap<&str, &str> = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .collect();
+            if labels
+                .iter()
+                .all(|(name, value)| values.get(name) == Some(value))
+            {
+                let _ = vec.remove(&values);
             }
-        })
-        .await
-        .unwrap();
+        }
+    }
 }
 
-async fn metrics_handler() -> impl IntoResponse {
-    let mut buffer = Vec::new();
-    let encoder = TextEncoder::new();
+// Set the series of `gauge` with these label values, or drop it when there is no value
+fn set_or_remove(gauge: &GaugeVec, labels: &[&str], value: Option<f64>) {
+    match value {
+        Some(value) => gauge.with_label_values(labels).set(value),
+        None => {
+            let _ = gauge.remove_label_values(labels);
+        }
+    }
+}
 
-    // Get correction factors
-    let correction_factors = CORRECTION_FACTORS.lock().unwrap().clone();
-    let default_correction = *DEFAULT_CORRECTION_FACTOR.lock().unwrap();
+// Drop the last-value series of one pair so a failing target stops exporting old numbers;
+// histograms are kept because they describe past probes
+fn remove_stale_series(interface: &str, server_ip: &str) {
+    let labels = [("interface", interface), ("server_ip", server_ip)];
+    for gauge in target_gauges() {
+        remove_series(gauge, &labels);
+    }
+}
 
-    // Gather metrics
-    let metric_families = REGISTRY.gather();
+// Reachability bookkeeping for every finished probe, called by the scheduler
+fn record_outcome(target: &Target, outcome: &Outcome) {
+    let interface = target.interface.as_str();
+    let server = target.server.as_str();
+
+    match outcome.failure {
+        None => {
+            PROBE_UP_GAUGE
+                .with_label_values(&[interface, server])
+                .set(1.0);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            LAST_SUCCESS_GAUGE
+                .with_label_values(&[interface, server])
+                .set(now);
+        }
+        Some(reason) => {
+            PROBE_UP_GAUGE
+                .with_label_values(&[interface, server])
+                .set(0.0);
+            PROBE_FAILURES_COUNTER
+                .with_label_values(&[interface, server, reason.as_str()])
+                .inc();
+            if reason == FailureReason::Bind {
+                INTERFACE_BOUND_GAUGE
+                    .with_label_values(&[interface])
+                    .set(0.0);
+            }
+            if reason == FailureReason::LinkDown {
+                // No server of the interface is reachable, drop them all at once
+                for gauge in target_gauges() {
+                    remove_series(gauge, &[("interface", interface)]);
+                }
+                let _ = BANDWIDTH_AVG_GAUGE.remove_label_values(&[interface]);
+                remove_series(&BANDWIDTH_AVG_SHARE_GAUGE, &[("interface", interface)]);
+            }
+            let key = (target.interface.clone(), target.server.clone());
+            if let Some(server_ip) = SERVER_IPS.lock().unwrap().get(&key) {
+                remove_stale_series(interface, server_ip);
+            }
+        }
+    }
+}
 
-    // Apply correction factor to all gauge values based on interface
-    let corrected_families: Vec<_> = metric_families
-        .iter()
-        .map(|mf| {
-            let mut corrected_mf = mf.clone();
-            for metric in corrected_mf.mut_metric() {
-                if metric.has_gauge() {
-                    let original_value = metric.get_gauge().get_value();
-
-                    // Find the interface label to get the correct correction factor
-                    let interface = metric
-                        .get_label()
-                        .iter()
-                        .find(|label| label.get_name() == "interface")
-                        .map(|label| label.get_value())
-                        .unwrap_or("");
+// Resolve the configuration; in auto mode the interfaces are the ones `links` has that
+// match the discovery filter of the configuration itself. `discovered` holds the interfaces
+// selected last time and is updated with the new selection.
+fn resolve_settings(
+    config: &Config,
+    overrides: &CliOverrides,
+    links: &[Link],
+    discovered: &mut Vec<String>,
+) -> Result<Settings, String> {
+    let settings = config.resolve(overrides)?;
+    let Some(discovery) = &settings.discovery else {
+        return Ok(settings);
+    };
+    let mut overrides = overrides.clone();
+    overrides.discovered = discovery.select(links, discovered);
+    let settings = config.resolve(&overrides)?;
+    *discovered = overrides.discovered;
+    Ok(settings)
+}
 
-                    let correction = correction_factors
-                        .get(interface)
-                        .copied()
-                        .unwrap_or(default_correction);
+// Re-reads the config file or takes a new interface scan and hands the resulting probe
+// plan to the running scheduler
+struct Reloader {
+    config: Option<PathBuf>,
+    overrides: CliOverrides,
+    // Listen addresses in use; the servers stay up across reloads
+    listen: (SocketAddr, SocketAddr),
+    // Whether the netlink watcher runs; started only in auto mode
+    watching: bool,
+    reloads: mpsc::Sender<Reload>,
+    // Configuration in effect, re-resolved when interfaces come and go
+    current: Mutex<Config>,
+    // Last interface scan
+    links: Mutex<Vec<Link>>,
+    // Interfaces selected from it in auto mode
+    discovered: Mutex<Vec<String>>,
+    sampler: Arc<Sampler>,
+}
 
-                    metric.mut_gauge().set_value(original_value * correction);
+impl Reloader {
+    async fn reload(&self, trigger: &str) -> Result<ReloadSummary, String> {
+        let path = self
+            .config
+            .as_ref()
+            .ok_or("Started without --config, nothing to reload")?;
+        let config = Config::load(path)?;
+        let links = self.links.lock().unwrap().clone();
+        let mut discovered = self.discovered.lock().unwrap().clone();
+        let settings = resolve_settings(&config, &self.overrides, &links, &mut discovered)?;
+        if settings.discovery.is_some() != self.watching {
+            eprintln!("Switching interface discovery on or off needs a restart");
+        }
+
+        let summary = self.apply(&settings).await?;
+        *self.current.lock().unwrap() = config;
+        *self.discovered.lock().unwrap() = discovered;
+        println!("Configuration reloaded ({}): {}", trigger, summary);
+        print_plan(&settings);
+        Ok(summary)
+    }
+
+    // New interface scan from the netlink watcher
+    async fn rediscover(&self, links: Vec<Link>) -> Result<ReloadSummary, String> {
+        *self.links.lock().unwrap() = links.clone();
+        let config = self.current.lock().unwrap().clone();
+        let mut discovered = self.discovered.lock().unwrap().clone();
+        let settings = resolve_settings(&config, &self.overrides, &links, &mut discovered)?;
+
+        let summary = self.apply(&settings).await?;
+        *self.discovered.lock().unwrap() = discovered;
+        if !(summary.added.is_empty() && summary.removed.is_empty()) {
+            println!("Interfaces changed: {}", summary);
+        }
+        Ok(summary)
+    }
+
+    async fn apply(&self, settings: &Settings) -> Result<ReloadSummary, String> {
+        self.sampler.update(counter_plan(settings));
+        if (settings.metrics_listen, settings.api_listen) != self.listen {
+            eprintln!(
+                "Listen address changes need a restart, still serving on {} and {}",
+                self.listen.0, self.listen.1
+            );
+        }
+        if STATE_FILE.lock().unwrap().as_ref() != Some(&settings.state_file) {
+            eprintln!("State file changes need a restart, keeping the current one");
+        }
+        *WAN_ALIASES.lock().unwrap() = settings.wan_aliases.clone();
+
+        let (reply, summary) = oneshot::channel();
+        let reload = Reload {
+            targets: settings.targets.clone(),
+            schedule: Schedule {
+                report_interval: settings.report_interval,
+                max_concurrency: settings.max_concurrency,
+            },
+            reply,
+        };
+        self.reloads
+            .send(reload)
+            .await
+            .map_err(|_| "Scheduler is not running".to_string())?;
+        let summary = summary
+            .await
+            .map_err(|_| "Scheduler is not running".to_string())?;
+
+        // Removed pairs stop reporting; drop their series instead of exporting stale values
+        for key in &summary.removed {
+            let (interface, server) = key;
+            WINDOWS.lock().unwrap().remove(key);
+            if let Some(server_ip) = SERVER_IPS.lock().unwrap().remove(key) {
+                let labels = [("interface", interface.as_str()), ("server_ip", &server_ip)];
+                for gauge in target_gauges() {
+                    remove_series(gauge, &labels);
+                }
+                for histogram in target_histograms() {
+                    remove_series(histogram, &labels);
                 }
             }
-            corrected_mf
-        })
-        .collect();
+            let labels = [("interface", interface.as_str()), ("server", server)];
+            remove_series(&PROBE_UP_GAUGE, &labels);
+            remove_series(&LAST_SUCCESS_GAUGE, &labels);
+            remove_series(&PROBE_FAILURES_COUNTER, &labels);
+            remove_series(&BANDWIDTH_AVG_SHARE_GAUGE, &labels);
+            if !settings.targets.iter().any(|t| &t.interface == interface) {
+                let _ = BANDWIDTH_AVG_GAUGE.remove_label_values(&[interface]);
+                let _ = INTERFACE_UP_GAUGE.remove_label_values(&[interface]);
+                LINK_STATES.lock().unwrap().remove(interface);
+            }
+        }
+        Ok(summary)
+    }
+}
 
-    encoder.encode(&corrected_families, &mut buffer).unwrap();
+// Interface counters are read from our sysfs, so interfaces in another namespace are left out
+fn counter_plan(settings: &Settings) -> counters::Plan {
+    counters::Plan {
+        interval: settings.counter_interval,
+        window: settings.counter_window,
+        interfaces: settings
+            .targets
+            .iter()
+            .filter(|t| t.options.route.netns.is_none())
+            .map(|t| (t.interface.clone(), t.device.clone()))
+            .collect(),
+    }
+}
 
-    (StatusCode::OK, buffer)
+// Auto mode: rescan on netlink link/address/route events and reconcile the probe plan
+async fn rediscover_on_netlink(reloader: Arc<Reloader>) {
+    let (scans, mut rx) = mpsc::unbounded_channel();
+    let current = reloader.links.lock().unwrap().clone();
+    std::thread::spawn(move || {
+        if let Err(e) = discovery::watch(current, |links| scans.send(links).is_ok()) {
+            eprintln!("Interface discovery stopped: {}", e);
+        }
+    });
+    while let Some(links) = rx.recv().await {
+        if let Err(e) = reloader.rediscover(links).await {
+            eprintln!("Failed to apply discovered interfaces: {}", e);
+        }
+    }
 }
 
-// HTTP correction server on port 32600
-async fn start_correction_server(running: Arc<AtomicBool>) {
-    let app = Router::new().route("/tcpflow", get(correction_handler));
+// Reload the configuration every time the process receives SIGHUP
+async fn reload_on_sighup(reloader: Arc<Reloader>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            eprintln!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        if let Err(e) = reloader.reload("SIGHUP").await {
+            eprintln!("Reload failed, keeping the previous configuration: {}", e);
+        }
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:32600")
-        .await
-        .unwrap();
+// Prometheus metrics server (port 59121 by default)
+async fn start_metrics_server(listen: SocketAddr, running: Arc<AtomicBool>) {
+    let app = Router::new().route("/metrics", get(metrics_handler));
+
+    let listener = tokio::net::TcpListener::bind(listen).await.unwrap();
 
     axum::serve(listener, app)
         .with_graceful_shutdown(async move {
@@ -141,48 +762,109 @@ async fn start_correction_server(running: Arc<AtomicBool>) {
         .unwrap();
 }
 
-async fn correction_handler(Query(params): Query<CorrectionQuery>) -> impl IntoResponse {
+async fn metrics_handler() -> impl IntoResponse {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+
+    // Raw values as measured; corrected values are separate series from DerivedCollector
+    let mut metric_families = REGISTRY.gather();
+    wan::label_families(&mut metric_families, &WAN_ALIASES.lock().unwrap());
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+
+    (StatusCode::OK, buffer)
+}
+
+// HTTP correction server (port 32600 by default)
+async fn start_correction_server(
+    listen: SocketAddr,
+    running: Arc<AtomicBool>,
+    reloader: Arc<Reloader>,
+) {
+    let app = Router::new()
+        .route("/tcpflow", get(correction_handler))
+        .route("/reload", post(reload_handler))
+        .nest("/api/v1", api::router())
+        .with_state(reloader);
+
+    let listener = tokio::net::TcpListener::bind(listen).await.unwrap();
+
+    // Peer addresses are recorded as the source of correction changes
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        while running.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+async fn correction_handler(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(params): Query<CorrectionQuery>,
+) -> impl IntoResponse {
     if let Some(value) = params.value {
-        if value > 0.0 {
-            if let Some(nic) = params.nic {
-                // Map wan name to actual interface (wan0 -> eth0, wan1 -> eth1)
+        if value > 0.0 && value.is_finite() {
+            let correction = Correction::new(value, format!("/tcpflow from {}", peer.ip()));
+            let (message, saved) = if let Some(nic) = params.nic {
+                // Map wan name to actual interface (e.g. wan0 -> eth0)
                 let interface = map_wan_to_interface(&nic);
-                CORRECTION_FACTORS
-                    .lock()
-                    .unwrap()
-                    .insert(interface.clone(), value);
-                (
-                    StatusCode::OK,
-                    format!(
-                        "Correction factor for {} ({}) set to: {}\n",
-                        nic, interface, value
-                    ),
-                )
+                let message = format!(
+                    "Correction factor for {} ({}) set to: {}\n",
+                    nic, interface, value
+                );
+                let saved = update_corrections(|c| {
+                    c.interfaces.insert(interface, correction);
+                })
+                .await;
+                (message, saved)
             } else {
                 // Set default correction factor for all interfaces
-                *DEFAULT_CORRECTION_FACTOR.lock().unwrap() = value;
+                let saved = update_corrections(|c| c.default = correction).await;
                 (
-                    StatusCode::OK,
                     format!("Default correction factor set to: {}\n", value),
+                    saved,
                 )
+            };
+            match saved {
+                Ok(()) => (StatusCode::OK, message),
+                Err(e) => {
+                    eprintln!("Failed to persist correction factors: {}", e);
+                    (
+                        StatusCode::OK,
+                        format!("{}Warning: not persisted: {}\n", message, e),
+                    )
+                }
             }
         } else {
             (
                 StatusCode::BAD_REQUEST,
-                "Value must be greater than 0\n".to_string(),
+                "Value must be a finite number greater than 0\n".to_string(),
             )
         }
     } else {
         // Display current correction factors
-        let correction_factors = CORRECTION_FACTORS.lock().unwrap().clone();
-        let default_correction = *DEFAULT_CORRECTION_FACTOR.lock().unwrap();
+        let corrections = CORRECTIONS.lock().unwrap().clone();
 
-        let mut response = format!("Default correction factor: {}\n", default_correction);
+        let mut response = format!(
+            "Default correction factor: {}\n",
+            corrections.default.factor
+        );
 
-        if !correction_factors.is_empty() {
+        if !corrections.interfaces.is_empty() {
+            let aliases = WAN_ALIASES.lock().unwrap();
             response.push_str("\nPer-interface correction factors:\n");
-            for (interface, factor) in correction_factors.iter() {
-                response.push_str(&format!("  {}: {}\n", interface, factor));
+            for (interface, correction) in corrections.interfaces.iter() {
+                let wan = aliases.wan(interface);
+                let name = if wan == interface {
+                    interface.clone()
+                } else {
+                    format!("{} ({})", wan, interface)
+                };
+                response.push_str(&format!("  {}: {}\n", name, correction.factor));
             }
         }
 
@@ -190,9 +872,37 @@ async fn correction_handler(Query(params): Query<CorrectionQuery>) -> impl IntoR
     }
 }
 
+// POST /reload: same as SIGHUP
+async fn reload_handler(State(reloader): State<Arc<Reloader>>) -> impl IntoResponse {
+    match reloader.reload("API").await {
+        Ok(summary) => (
+            StatusCode::OK,
+            format!("Configuration reloaded: {}\n", summary),
+        ),
+        Err(e) => {
+            eprintln!("Reload failed, keeping the previous configuration: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Reload failed, keeping the previous configuration: {}\n", e),
+            )
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML configuration file; command line options override its values
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Validate the configuration, print the resolved probe plan and exit
+    #[arg(long)]
+    check_config: bool,
+
     /// Network interfaces to use (can specify multiple)
     #[arg(short, long, action = clap::ArgAction::Append)]
     interface: Vec<String>,
@@ -200,413 +910,920 @@ struct Args {
     /// Server IP addresses to measure
     #[arg(short, long, action = clap::ArgAction::Append)]
     server: Vec<String>,
-}
 
-fn main() {
-    let args = Args::parse();
+    /// Probe type: cheap window/RTT estimate, bulk download, HTTP(S) fetch or reflector [default: estimate]
+    #[arg(long, value_enum)]
+    probe: Option<ProbeMode>,
 
-    if args.interface.is_empty() {
-        eprintln!("No interfaces specified. Use -i/--interface to add interfaces.");
-        std::process::exit(2);
-    }
+    /// HTTP probe: object to fetch, either `<URL>` for all servers or `<SERVER>=<URL>` (can specify multiple)
+    #[arg(short, long, action = clap::ArgAction::Append)]
+    url: Vec<String>,
 
-    if args.server.is_empty() {
-        eprintln!("No servers specified. Use -s/--server to add targets.");
-        std::process::exit(2);
-    }
+    /// Bulk/HTTP probe: stop after receiving this many bytes [default: 10000000]
+    #[arg(long)]
+    bulk_bytes: Option<u64>,
 
-    // Ctrl+C handling
-    let running = Arc::new(AtomicBool::new(true));
-    {
-        let running = running.clone();
-        let _ = ctrlc::set_handler(move || {
-            running.store(false, Ordering::SeqCst);
-        });
-    }
+    /// Bulk/HTTP probe: stop after this many seconds [default: 5]
+    #[arg(long)]
+    bulk_duration: Option<f64>,
 
-    // Create tokio runtime for async servers
-    let rt = Runtime::new().unwrap();
+    /// Bulk probe: payload sent after connecting (\r and \n escapes are expanded)
+    #[arg(long)]
+    bulk_request: Option<String>,
 
-    // Start Prometheus metrics server (port 59121)
-    {
-        let running = running.clone();
-        rt.spawn(async move {
-            start_metrics_server(running).await;
-        });
-    }
+    /// Bandwidth model behind tcp_bandwidth_bps [default: goodput, buffer for the estimate probe]
+    #[arg(long, value_enum)]
+    model: Option<Model>,
 
-    // Start HTTP correction server (port 32600)
-    {
-        let running = running.clone();
-        rt.spawn(async move {
-            start_correction_server(running).await;
-        });
-    }
+    /// Buffer model: TCP efficiency factor applied to window/RTT [default: 0.75]
+    #[arg(long)]
+    efficiency: Option<f64>,
 
-    println!("Prometheus metrics available at http://localhost:59121/metrics");
-    println!("Correction factor API available at http://localhost:32600/tcpflow?value=<factor>");
-    println!("Interfaces: {:?}", args.interface);
-    println!("Servers: {:?}", args.server);
-    println!("Starting measurements...");
-    println!("==================================");
+    /// Seconds between two probes of the same interface/server pair [default: 1]
+    #[arg(long)]
+    interval: Option<f64>,
 
-    // Main loop until Ctrl+C
-    let sleep_duration = Duration::from_secs_f64(1.0);
-    while running.load(Ordering::SeqCst) {
-        for interface in &args.interface {
-            let mut results = Vec::new();
-            let mut bandwidth_sum = 0.0;
-            let mut bandwidth_count = 0;
-
-            for server_str in &args.server {
-                match resolve_server_address(server_str) {
-                    Ok(server_addr) => match measure_throughput(interface, server_addr) {
-                        Ok((rtt, window_size)) => {
-                            // Calculate bandwidth using improved formula
-                            // BDP (Bandwidth-Delay Product) = Bandwidth × RTT
-                            // Therefore: Bandwidth = Window Size / RTT
-                            //
-                            // We use a more conservative approach:
-                            // - Convert window size to bits (multiply by 8)
-                            // - Divide by RTT in seconds
-                            // - Apply a coefficient to account for TCP overhead and protocol efficiency
-                            let rtt_secs = rtt.as_secs_f64();
-                            let throughput_bps = if rtt_secs > 0.0 {
-                                // TCP typically achieves 70-90% efficiency due to:
-                                // - ACK overhead
-                                // - Retransmissions
-                                // - Slow start and congestion control
-                                // We use 0.75 as a reasonable efficiency factor
-                                let raw_bandwidth = (window_size as f64 * 8.0) / rtt_secs;
-                                let tcp_efficiency = 0.75;
-                                raw_bandwidth * tcp_efficiency
-                            } else {
-                                0.0
-                            };
-
-                            // Update Prometheus metric (in bps)
-                            BANDWIDTH_GAUGE
-                                .with_label_values(&[interface, &server_addr.ip().to_string()])
-                                .set(throughput_bps);
-
-                            eprintln!(
-                                "DEBUG: Set metric for interface={}, server_ip={}, throughput_bps={}",
-                                interface,
-                                server_addr.ip(),
-                                throughput_bps
-                            );
-
-                            // Accumulate for average
-                            bandwidth_sum += throughput_bps;
-                            bandwidth_count += 1;
-
-                            // Format with RTT information for debugging
-                            results.push(format!(
-                                "{}:{:.0}bps(rtt:{:.1}ms,win:{})",
-                                server_addr.ip(),
-                                throughput_bps,
-                                rtt.as_secs_f64() * 1000.0,
-                                window_size
-                            ));
-                        }
-                        Err(e) => {
-                            eprintln!(
-                                "Error measuring {} on {}: {}",
-                                server_addr.ip(),
-                                interface,
-                                e
-                            );
-                            results.push(format!("{}:ERR", server_addr.ip()));
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("Error resolving server address for {}: {}", server_str, e);
-                        results.push(format!("{}:N/A", server_str));
-                    }
-                }
+    /// Maximum number of probes running at the same time [default: 4]
+    #[arg(long)]
+    max_concurrency: Option<usize>,
 
-                // Small delay between servers to stagger measurements
-                std::thread::sleep(Duration::from_millis(100));
-            }
+    /// Responsiveness probe: parallel downloads that load the link [default: 4]
+    #[arg(long)]
+    flows: Option<usize>,
 
-            // Calculate and update average bandwidth for this interface
-            if bandwidth_count > 0 {
-                let avg_bandwidth = bandwidth_sum / bandwidth_count as f64;
-                BANDWIDTH_AVG_GAUGE
-                    .with_label_values(&[interface])
-                    .set(avg_bandwidth);
+    /// Seconds to wait for the TCP handshake [default: 5]
+    #[arg(long)]
+    connect_timeout: Option<f64>,
 
-                results.push(format!("avg:{:.0}bps", avg_bandwidth));
-            }
+    /// Seconds after which a running probe is reported as timed out [default: 10]
+    #[arg(long)]
+    probe_timeout: Option<f64>,
+
+    /// Fail probes whose socket cannot be bound to the interface instead of using the default route.
+    /// Binding to an interface that does not exist (ENODEV) fails in either mode
+    #[arg(long)]
+    strict_bind: bool,
+
+    /// Address of the Prometheus metrics server [default: 0.0.0.0:59121]
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Address of the correction factor API [default: 0.0.0.0:32600]
+    #[arg(long)]
+    api_listen: Option<SocketAddr>,
+
+    /// File the correction factors are persisted to [default: corrections.json]
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    /// Probe the interfaces found via netlink and follow them as they come and go
+    #[arg(long, conflicts_with = "interface")]
+    auto: bool,
+
+    /// Auto mode: only interfaces whose name matches, `*` and `?` allowed (can specify multiple)
+    #[arg(long, action = clap::ArgAction::Append)]
+    auto_include: Vec<String>,
+
+    /// Auto mode: only interfaces a default route goes out of
+    #[arg(long)]
+    auto_default_route: bool,
 
-            // Print interface results in bar format
-            println!("{}: |{}|", interface, results.join("|"));
+    /// Seconds between two readings of the interface counters, 0 disables them [default: 0.25]
+    #[arg(long)]
+    counter_interval: Option<f64>,
 
-            // Delay between interfaces to stagger measurements
-            std::thread::sleep(Duration::from_millis(200));
+    /// Seconds of probe results kept per interface/server pair for the statistics [default: 60]
+    #[arg(long)]
+    stats_window: Option<f64>,
+
+    /// How the servers of an interface are combined into its bandwidth [default: mean]
+    #[arg(long, value_enum)]
+    aggregate: Option<Aggregate>,
+
+    /// Logical WAN name of an interface as `<WAN>=<INTERFACE>`, e.g. wan0=enp1s0 (can specify multiple)
+    #[arg(long, value_parser = parse_wan_alias, action = clap::ArgAction::Append)]
+    wan: Vec<(String, String)>,
+}
+
+fn parse_wan_alias(spec: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((wan, interface)) if !wan.is_empty() && !interface.is_empty() => {
+            Ok((wan.to_string(), interface.to_string()))
         }
+        _ => Err(format!("expected <WAN>=<INTERFACE>, got '{}'", spec)),
+    }
+}
 
-        let _ = std::io::stdout().flush();
+impl Args {
+    // Command line values that take precedence over the config file
+    fn overrides(&self) -> CliOverrides {
+        let mut settings = ProbeSettings {
+            interval: self.interval,
+            probe: self.probe,
+            bytes: self.bulk_bytes,
+            duration: self.bulk_duration,
+            request: self.bulk_request.clone(),
+            flows: self.flows,
+            efficiency: self.efficiency,
+            model: self.model,
+            connect_timeout: self.connect_timeout,
+            probe_timeout: self.probe_timeout,
+            strict_bind: self.strict_bind.then_some(true),
+            ..ProbeSettings::default()
+        };
 
-        // Sleep until next iteration or exit if Ctrl+C was pressed
-        let start_sleep = Instant::now();
-        while running.load(Ordering::SeqCst) {
-            let elapsed = start_sleep.elapsed();
-            if elapsed >= sleep_duration {
-                break;
+        // `-u <SERVER>=<URL>` unless the part before '=' is already a URL (query strings contain '=')
+        let mut server_urls = HashMap::new();
+        for spec in &self.url {
+            match spec.split_once('=') {
+                Some((server, url)) if !server.contains("://") => {
+                    server_urls.insert(server.to_string(), url.to_string());
+                }
+                _ => settings.url = Some(spec.clone()),
             }
-            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let mut discovery = DiscoveryConfig::default();
+        discovery.enabled = self.auto.then_some(true);
+        discovery.include = (!self.auto_include.is_empty()).then(|| self.auto_include.clone());
+        discovery.default_route = self.auto_default_route.then_some(true);
+
+        CliOverrides {
+            interfaces: self.interface.clone(),
+            servers: self.server.clone(),
+            settings,
+            server_urls,
+            metrics_listen: self.metrics_listen,
+            api_listen: self.api_listen,
+            max_concurrency: self.max_concurrency,
+            state_file: self.state_file.clone(),
+            counter_interval: self.counter_interval,
+            stats_window: self.stats_window,
+            aggregate: self.aggregate,
+            wan_aliases: self.wan.clone(),
+            discovery,
+            discovered: Vec::new(),
         }
     }
+}
 
-    println!("\nShutting down...");
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a reflector that reflector probes from other tcp-traffic-scan instances measure against
+    Serve {
+        /// Address to listen on
+        #[arg(short, long, default_value_t = SocketAddr::from(([0, 0, 0, 0], reflector::DEFAULT_PORT)))]
+        listen: SocketAddr,
+
+        /// Upper bound on the bytes sent per download request
+        #[arg(long, default_value_t = 1_000_000_000)]
+        max_bytes: u64,
+
+        /// Upper bound on the duration of a download request in seconds
+        #[arg(long, default_value_t = 30.0)]
+        max_duration: f64,
+    },
 }
 
-fn resolve_server_address(server_str: &str) -> io::Result<SocketAddr> {
-    // Append a default port if not specified, required by ToSocketAddrs
-    let addr_with_port = if server_str.contains(':') {
-        server_str.to_string()
-    } else {
-        format!("{}:443", server_str) // Default to port 443 for resolution
+// Run the configured probe, falling back to the cheap estimator if the active transfer fails
+fn run_probe(target: &Target, addr: SocketAddr) -> io::Result<Measurement> {
+    let interface = target.device.as_str();
+    let server_str = target.server.as_str();
+    let options = &target.options;
+
+    let result = match target.probe {
+        ProbeMode::Estimate => return measure_throughput(interface, addr, options),
+        ProbeMode::Bulk => measure_goodput(interface, addr, options),
+        ProbeMode::Http => match &target.url {
+            Some(url) => {
+                // Speak to the port of the URL unless the server was given with an explicit one
+                let addr = if server_str.contains(':') {
+                    addr
+                } else {
+                    SocketAddr::new(addr.ip(), url_port(url))
+                };
+                measure_http(interface, addr, url, options)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No URL configured for server {}", server_str),
+            )),
+        },
+        ProbeMode::Reflector => {
+            let addr = if server_str.contains(':') {
+                addr
+            } else {
+                SocketAddr::new(addr.ip(), reflector::DEFAULT_PORT)
+            };
+            measure_reflector(interface, addr, options)
+        }
+        ProbeMode::Responsiveness => measure_responsiveness(interface, addr, options),
     };
 
-    addr_with_port
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not resolve address"))
+    // The estimate probe transfers no data, so there is nothing to fall back to for the
+    // goodput model; the failure is reported with its own reason instead
+    if target.model == Model::Goodput {
+        return result;
+    }
+    result.or_else(|e| {
+        eprintln!(
+            "{:?} probe to {} on {} failed ({}), falling back to estimate",
+            target.probe, server_str, target.interface, e
+        );
+        measure_throughput(interface, addr, options)
+    })
 }
 
-fn measure_throughput(interface: &str, addr: SocketAddr) -> io::Result<(Duration, u32)> {
-    let domain = if addr.is_ipv4() {
-        Domain::IPV4
-    } else {
-        Domain::IPV6
+// Export the link state of an interface and log when it changes
+fn note_link_state(interface: &str, up: bool) {
+    INTERFACE_UP_GAUGE
+        .with_label_values(&[interface])
+        .set(if up { 1.0 } else { 0.0 });
+    let previous = LINK_STATES
+        .lock()
+        .unwrap()
+        .insert(interface.to_string(), up);
+    if previous != Some(up) && (previous.is_some() || !up) {
+        let state = if up {
+            "up"
+        } else {
+            "down, skipping its probes"
+        };
+        eprintln!("Link {} is {}", interface, state);
+    }
+}
+
+// Resolve and probe one interface/server pair; runs on the blocking pool. Nothing is
+// exported here, the returned closure records the result once the scheduler publishes it.
+fn probe_once(target: &Target) -> Publish {
+    // A link without carrier would only run into the connect timeout. Devices in another
+    // namespace are not visible in our sysfs, those are always probed.
+    let up = match target.options.route.netns {
+        Some(_) => None,
+        None => link_up(&target.device),
+    };
+    let probed = match up {
+        Some(false) => Err(Outcome {
+            bandwidth_bps: None,
+            summary: format!("{}:DOWN", target.server),
+            failure: Some(FailureReason::LinkDown),
+        }),
+        _ => measure(target),
     };
 
-    let socket = Socket::new(domain, Type::STREAM, None)?;
+    Box::new(move |target: &Target| {
+        if let Some(up) = up {
+            note_link_state(&target.interface, up);
+        }
+        let (server_addr, measurement, throughput_bps) = match probed {
+            Ok(probed) => probed,
+            Err(outcome) => return outcome,
+        };
+        let detail = record_measurement(target, server_addr, &measurement, throughput_bps);
+        let summary = record_window(target, server_addr, throughput_bps);
+        Outcome {
+            bandwidth_bps: Some(summary.median),
+            summary: format!("{}:{:.0}bps({})", server_addr.ip(), throughput_bps, detail),
+            failure: None,
+        }
+    })
+}
 
-    // Set socket options before connecting for better control
-    socket.set_nodelay(true)?; // Disable Nagle's algorithm for faster response
-    socket.set_keepalive(true)?; // Enable keepalive to maintain connection state
+// Resolve and probe the server of a pair; returns the measurement with the bandwidth of the
+// configured model, or the outcome of the failed probe
+fn measure(target: &Target) -> Result<(SocketAddr, Measurement, f64), Outcome> {
+    let interface = target.interface.as_str();
+    let server_str = target.server.as_str();
+
+    match resolve_server_address(server_str) {
+        Ok(server_addr) => match run_probe(target, server_addr) {
+            Ok(measurement) => match target.model.estimate(&measurement, target.efficiency) {
+                Some(throughput_bps) => Ok((server_addr, measurement, throughput_bps)),
+                None => {
+                    eprintln!(
+                        "Probe of {} on {} has no data for the {} model",
+                        server_addr.ip(),
+                        interface,
+                        target.model.as_str()
+                    );
+                    Err(Outcome {
+                        bandwidth_bps: None,
+                        summary: format!("{}:N/A", server_addr.ip()),
+                        failure: Some(FailureReason::NoEstimate),
+                    })
+                }
+            },
+            Err(e) => {
+                eprintln!(
+                    "Error measuring {} on {}: {}",
+                    server_addr.ip(),
+                    interface,
+                    e
+                );
+                Err(Outcome {
+                    bandwidth_bps: None,
+                    summary: format!("{}:ERR", server_addr.ip()),
+                    failure: Some(FailureReason::of(&e)),
+                })
+            }
+        },
+        Err(e) => {
+            eprintln!("Error resolving server address for {}: {}", server_str, e);
+            Err(Outcome {
+                bandwidth_bps: None,
+                summary: format!("{}:N/A", server_str),
+                failure: Some(FailureReason::of(&e)),
+            })
+        }
+    }
+}
 
-    // Bind the socket to the specified interface (Linux-only)
-    if let Err(e) = bind_socket_to_interface(&socket, interface) {
-        eprintln!(
-            "Warning: Failed to bind to device '{}'. This might require root privileges. Error: {}",
-            interface, e
+// Add a successful probe to the rolling window of its pair and export the window statistics
+fn record_window(target: &Target, server_addr: SocketAddr, throughput_bps: f64) -> Summary {
+    let key = (target.interface.clone(), target.server.clone());
+    let summary = WINDOWS.lock().unwrap().entry(key).or_default().push(
+        Instant::now(),
+        throughput_bps,
+        &target.statistics,
+    );
+
+    let interface = target.interface.as_str();
+    let server_ip = server_addr.ip().to_string();
+    for (statistic, value) in [
+        ("median", summary.median),
+        ("p10", summary.p10),
+        ("p90", summary.p90),
+        ("ewma", summary.ewma),
+    ] {
+        BANDWIDTH_WINDOW_GAUGE
+            .with_label_values(&[interface, &server_ip, statistic])
+            .set(value);
+    }
+    for (kind, count) in [("total", summary.samples), ("outliers", summary.outliers)] {
+        BANDWIDTH_WINDOW_SAMPLES_GAUGE
+            .with_label_values(&[interface, &server_ip, kind])
+            .set(count as f64);
+    }
+    summary
+}
+
+// Update Prometheus metrics for one successful probe and return the bar detail text
+fn record_measurement(
+    target: &Target,
+    server_addr: SocketAddr,
+    measurement: &Measurement,
+    throughput_bps: f64,
+) -> String {
+    let interface = target.interface.as_str();
+    let server_ip = server_addr.ip().to_string();
+
+    // A server that moved to another address leaves its old series behind
+    let key = (target.interface.clone(), target.server.clone());
+    if let Some(old_ip) = SERVER_IPS
+        .lock()
+        .unwrap()
+        .insert(key.clone(), server_ip.clone())
+    {
+        if old_ip != server_ip {
+            // Samples of the old address say nothing about the new one
+            WINDOWS.lock().unwrap().remove(&key);
+            remove_stale_series(interface, &old_ip);
+        }
+    }
+
+    // Update Prometheus metric (in bps)
+    BANDWIDTH_GAUGE
+        .with_label_values(&[interface, &server_ip])
+        .set(throughput_bps);
+
+    // Every model the measurement has data for, for comparison with the configured one
+    for model in Model::ALL {
+        let labels = [interface, server_ip.as_str(), model.as_str()];
+        set_or_remove(
+            &BANDWIDTH_MODEL_GAUGE,
+            &labels,
+            model.estimate(measurement, target.efficiency),
         );
-        // Continue without binding, the OS will choose the interface.
     }
 
-    // Measure connection establishment time (includes SYN, SYN-ACK, ACK)
-    let start = Instant::now();
-    socket.connect_timeout(&addr.into(), Duration::from_secs(5))?;
-    let connect_time = start.elapsed();
+    let loss = model::loss_rate(measurement);
+    set_or_remove(&LOSS_GAUGE, &[interface, &server_ip], loss);
+
+    INTERFACE_BOUND_GAUGE
+        .with_label_values(&[interface])
+        .set(if measurement.bound { 1.0 } else { 0.0 });
+
+    let labels = [interface, server_ip.as_str()];
+    let rtt = measurement.rtt.as_secs_f64();
+    let connect = measurement.connect_time.as_secs_f64();
+    RTT_GAUGE.with_label_values(&labels).set(rtt);
+    RTT_HISTOGRAM.with_label_values(&labels).observe(rtt);
+    CONNECT_GAUGE.with_label_values(&labels).set(connect);
+    CONNECT_HISTOGRAM
+        .with_label_values(&labels)
+        .observe(connect);
+    WINDOW_GAUGE
+        .with_label_values(&labels)
+        .set(measurement.window_size as f64);
+    // Values the probe did not produce this time are removed, not left at their last value
+    let info = measurement.tcp_info.as_ref();
+    if let Some(info) = info {
+        RTTVAR_HISTOGRAM
+            .with_label_values(&labels)
+            .observe(info.rttvar.as_secs_f64());
+    }
+    set_or_remove(&RTTVAR_GAUGE, &labels, info.map(|i| i.rttvar.as_secs_f64()));
+    set_or_remove(&SND_CWND_GAUGE, &labels, info.map(|i| i.snd_cwnd as f64));
+    set_or_remove(&MSS_GAUGE, &labels, info.map(|i| i.snd_mss as f64));
+
+    let goodput = measurement.goodput.as_ref();
+    for (phase, value) in [
+        ("ramp_up", goodput.map(|g| g.ramp_up.bps())),
+        ("steady", goodput.map(|g| g.steady.bps())),
+    ] {
+        set_or_remove(&GOODPUT_GAUGE, &[interface, &server_ip, phase], value);
+    }
+
+    let http = measurement.http.as_ref();
+    set_or_remove(
+        &HTTP_TTFB_GAUGE,
+        &labels,
+        http.map(|h| h.ttfb.as_secs_f64()),
+    );
+    set_or_remove(
+        &HTTP_TLS_HANDSHAKE_GAUGE,
+        &labels,
+        http.and_then(|h| h.tls_handshake).map(|t| t.as_secs_f64()),
+    );
+    set_or_remove(
+        &HTTP_THROUGHPUT_GAUGE,
+        &labels,
+        http.and(goodput).map(|g| g.total.bps()),
+    );
+
+    let reflector = measurement.reflector.as_ref();
+    set_or_remove(
+        &REFLECTOR_ECHO_RTT_GAUGE,
+        &labels,
+        reflector
+            .and_then(|r| r.echo_rtt)
+            .map(|rtt| rtt.as_secs_f64()),
+    );
+    set_or_remove(
+        &REFLECTOR_SERVER_SEND_GAUGE,
+        &labels,
+        reflector
+            .and_then(|r| r.server.as_ref())
+            .map(|s| s.send_bps()),
+    );
+
+    let responsiveness = measurement.responsiveness.as_ref();
+    for (load, value) in [
+        ("idle", responsiveness.map(|r| r.idle_rtt.as_secs_f64())),
+        ("loaded", responsiveness.map(|r| r.loaded_rtt.as_secs_f64())),
+    ] {
+        set_or_remove(
+            &RESPONSIVENESS_RTT_GAUGE,
+            &[interface, &server_ip, load],
+            value,
+        );
+    }
+    set_or_remove(
+        &RESPONSIVENESS_RPM_GAUGE,
+        &labels,
+        responsiveness.map(|r| r.rpm()),
+    );
+
+    // Format with RTT information for debugging
+    let mut detail = format!(
+        "rtt:{:.1}ms,win:{}",
+        measurement.rtt.as_secs_f64() * 1000.0,
+        measurement.window_size
+    );
+    if let Some(goodput) = &measurement.goodput {
+        detail.push_str(&format!(
+            ",ramp:{:.0}bps,steady:{:.0}bps,bytes:{}",
+            goodput.ramp_up.bps(),
+            goodput.steady.bps(),
+            goodput.total.bytes
+        ));
+    }
+    if let Some(http) = &measurement.http {
+        detail.push_str(&format!(
+            ",status:{},connect:{:.1}ms,ttfb:{:.1}ms",
+            http.status,
+            http.connect.as_secs_f64() * 1000.0,
+            http.ttfb.as_secs_f64() * 1000.0
+        ));
+        if let Some(handshake) = http.tls_handshake {
+            detail.push_str(&format!(",tls:{:.1}ms", handshake.as_secs_f64() * 1000.0));
+        }
+    }
+    if let Some(stats) = &measurement.reflector {
+        if let Some(echo_rtt) = stats.echo_rtt {
+            detail.push_str(&format!(",echo:{:.1}ms", echo_rtt.as_secs_f64() * 1000.0));
+        }
+        if let Some(server) = &stats.server {
+            detail.push_str(&format!(
+                ",server:{:.0}bps,server_retrans:{}",
+                server.send_bps(),
+                server.total_retrans
+            ));
+        }
+    }
+    if let Some(responsiveness) = &measurement.responsiveness {
+        detail.push_str(&format!(
+            ",idle:{:.1}ms,loaded:{:.1}ms,rpm:{:.0},flows:{},samples:{}",
+            responsiveness.idle_rtt.as_secs_f64() * 1000.0,
+            responsiveness.loaded_rtt.as_secs_f64() * 1000.0,
+            responsiveness.rpm(),
+            responsiveness.flows,
+            responsiveness.loaded_samples
+        ));
+    }
+    if let Some(loss) = loss {
+        detail.push_str(&format!(",loss:{:.2}%", loss * 100.0));
+    }
+    if !measurement.bound {
+        detail.push_str(",unbound");
+    }
+    if let Some(info) = &measurement.tcp_info {
+        detail.push_str(&format!(
+            ",rttvar:{:.1}ms,cwnd:{},mss:{}",
+            info.rttvar.as_secs_f64() * 1000.0,
+            info.snd_cwnd,
+            info.snd_mss
+        ));
+    }
+    detail
+}
+
+// Print the resolved interface/server pairs and their probe settings
+fn print_plan(settings: &Settings) {
+    if let Some(discovery) = &settings.discovery {
+        println!("Interface discovery: {}", discovery);
+    }
+    if let Some(interval) = settings.counter_interval {
+        println!(
+            "Interface counters: every {:?}, averaged over {:?}",
+            interval, settings.counter_window
+        );
+    }
+    let statistics = &settings.statistics;
+    match statistics.outlier_mad {
+        Some(mad) => println!(
+            "Bandwidth statistics: window {:?}, EWMA alpha {}, outliers beyond {} MADs",
+            statistics.window, statistics.ewma_alpha, mad
+        ),
+        None => println!(
+            "Bandwidth statistics: window {:?}, EWMA alpha {}, no outlier rejection",
+            statistics.window, statistics.ewma_alpha
+        ),
+    }
+    for target in &settings.targets {
+        let mut line = format!(
+            "{} -> {}: probe={:?} interval={:?} timeout={:?}",
+            target.interface, target.server, target.probe, target.interval, target.probe_timeout
+        );
+        if target.probe != ProbeMode::Estimate {
+            line.push_str(&format!(
+                " bytes={} duration={:?}",
+                target.options.bytes, target.options.duration
+            ));
+        }
+        if target.probe == ProbeMode::Responsiveness {
+            line.push_str(&format!(" flows={}", target.options.flows));
+        }
+        line.push_str(&format!(" model={}", target.model.as_str()));
+        if matches!(target.model, Model::Buffer | Model::Mathis) {
+            line.push_str(&format!(" efficiency={}", target.efficiency));
+        }
+        if let Some(url) = &target.url {
+            line.push_str(&format!(" url={}", url));
+        }
+        if target.aggregate != Aggregate::Mean {
+            line.push_str(&format!(" aggregate={:?}", target.aggregate));
+        }
+        if target.priority != 1.0 {
+            line.push_str(&format!(" priority={}", target.priority));
+        }
+        let route = &target.options.route;
+        if !route.bind_device {
+            line.push_str(" bind_device=false");
+        }
+        if let Some(mark) = route.fwmark {
+            line.push_str(&format!(" fwmark={:#x}", mark));
+        }
+        if let Some(source) = route.source {
+            line.push_str(&format!(" source={}", source));
+        }
+        if let Some(netns) = &route.netns {
+            line.push_str(&format!(" netns={}", netns.display()));
+        }
+        if let Some(vrf) = &route.vrf {
+            line.push_str(&format!(" vrf={}", vrf));
+        }
+        if target.options.strict_bind {
+            line.push_str(" strict_bind");
+        }
+        println!("{}", line);
+    }
+    let aliases: Vec<String> = settings
+        .wan_aliases
+        .iter()
+        .map(|(wan, interface)| format!("{}={}", wan, interface))
+        .collect();
+    println!("WAN aliases: {}", aliases.join(" "));
+}
 
-    // RTT is approximately half of the connection time (SYN -> SYN-ACK)
-    // This is more accurate than using the full connect_timeout duration
-    let estimated_rtt = connect_time / 2;
+// `serve` subcommand: run the reflector until Ctrl+C
+fn run_reflector(options: ServeOptions) {
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        let _ = ctrlc::set_handler(move || {
+            running.store(false, Ordering::SeqCst);
+        });
+    }
 
-    let fd = socket.as_raw_fd();
+    println!("Reflector listening on {}", options.listen);
 
-    // Wait a bit to let TCP connection fully establish and negotiate window
-    std::thread::sleep(Duration::from_millis(10));
+    let rt = Runtime::new().unwrap();
+    if let Err(e) = rt.block_on(reflector::serve(options, running)) {
+        eprintln!("Reflector failed: {}", e);
+        std::process::exit(1);
+    }
 
-    // Get TCP_INFO for more accurate RTT measurement
-    let actual_rtt = get_tcp_info_rtt(fd).unwrap_or(estimated_rtt);
+    println!("\nShutting down...");
+}
 
-    // Get receive buffer size (SO_RCVBUF)
-    let mut rcv_buf: libc::c_int = 0;
-    let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+fn main() {
+    let args = Args::parse();
 
-    let result = unsafe {
-        libc::getsockopt(
-            fd,
-            libc::SOL_SOCKET,
-            libc::SO_RCVBUF,
-            &mut rcv_buf as *mut _ as *mut libc::c_void,
-            &mut optlen,
-        )
+    if let Some(Command::Serve {
+        listen,
+        max_bytes,
+        max_duration,
+    }) = args.command
+    {
+        run_reflector(ServeOptions {
+            listen,
+            max_bytes,
+            max_duration: Duration::from_secs_f64(max_duration),
+        });
+        return;
+    }
+
+    let config = match &args.config {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    };
+    let config = config.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    let overrides = args.overrides();
+    let settings = config.resolve(&overrides).and_then(|settings| {
+        // Auto mode starts from a scan of the interfaces present now
+        let links = match settings.discovery {
+            Some(_) => discovery::scan()
+                .map_err(|e| format!("Failed to list interfaces via netlink: {}", e))?,
+            None => Vec::new(),
+        };
+        let mut discovered = Vec::new();
+        let settings = resolve_settings(&config, &overrides, &links, &mut discovered)?;
+        Ok((settings, links, discovered))
+    });
+    let (settings, links, discovered) = match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
     };
 
-    if result != 0 {
-        return Err(io::Error::last_os_error());
+    if args.check_config {
+        print_plan(&settings);
+        println!("Configuration OK");
+        return;
     }
 
-    // Linux doubles the returned value for internal bookkeeping
-    #[cfg(target_os = "linux")]
-    let actual_rcv_buf = (rcv_buf / 2) as u32;
+    *WAN_ALIASES.lock().unwrap() = settings.wan_aliases.clone();
+    load_corrections(settings.state_file.clone());
 
-    #[cfg(not(target_os = "linux"))]
-    let actual_rcv_buf = rcv_buf as u32;
+    // Ctrl+C handling
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        let _ = ctrlc::set_handler(move || {
+            running.store(false, Ordering::SeqCst);
+        });
+    }
 
-    // Get send buffer size (SO_SNDBUF) as well for better estimation
-    let mut snd_buf: libc::c_int = 0;
-    let result = unsafe {
-        libc::getsockopt(
-            fd,
-            libc::SOL_SOCKET,
-            libc::SO_SNDBUF,
-            &mut snd_buf as *mut _ as *mut libc::c_void,
-            &mut optlen,
-        )
-    };
+    // Create tokio runtime for async servers
+    let rt = Runtime::new().unwrap();
 
-    let actual_snd_buf = if result == 0 {
-        #[cfg(target_os = "linux")]
-        let buf = (snd_buf / 2) as u32;
-        #[cfg(not(target_os = "linux"))]
-        let buf = snd_buf as u32;
-        buf
-    } else {
-        actual_rcv_buf // Fallback to receive buffer
-    };
-
-    // Use the minimum of send and receive buffer as the effective window
-    // This better represents the actual TCP window limitation
-    let effective_window = std::cmp::min(actual_rcv_buf, actual_snd_buf);
-
-    Ok((actual_rtt, effective_window))
-}
-
-#[cfg(target_os = "linux")]
-fn get_tcp_info_rtt(fd: i32) -> Option<Duration> {
-    // Use TCP_INFO to get accurate RTT measurement on Linux
-    #[repr(C)]
-    struct TcpInfo {
-        tcpi_state: u8,
-        tcpi_ca_state: u8,
-        tcpi_retransmits: u8,
-        tcpi_probes: u8,
-        tcpi_backoff: u8,
-        tcpi_options: u8,
-        tcpi_snd_wscale_rcv_wscale: u8,
-        tcpi_delivery_rate_app_limited_fastopen_client_fail: u8,
-        tcpi_rto: u32,
-        tcpi_ato: u32,
-        tcpi_snd_mss: u32,
-        tcpi_rcv_mss: u32,
-        tcpi_unacked: u32,
-        tcpi_sacked: u32,
-        tcpi_lost: u32,
-        tcpi_retrans: u32,
-        tcpi_fackets: u32,
-        tcpi_last_data_sent: u32,
-        tcpi_last_ack_sent: u32,
-        tcpi_last_data_recv: u32,
-        tcpi_last_ack_recv: u32,
-        tcpi_pmtu: u32,
-        tcpi_rcv_ssthresh: u32,
-        tcpi_rtt: u32,    // Smoothed RTT in microseconds
-        tcpi_rttvar: u32, // RTT variance in microseconds
-        tcpi_snd_ssthresh: u32,
-        tcpi_snd_cwnd: u32,
-        tcpi_advmss: u32,
-        tcpi_reordering: u32,
-        // ... more fields exist but we only need RTT
-    }
-
-    let mut info: TcpInfo = unsafe { std::mem::zeroed() };
-    let mut len = std::mem::size_of::<TcpInfo>() as libc::socklen_t;
-
-    let result = unsafe {
-        libc::getsockopt(
-            fd,
-            libc::IPPROTO_TCP,
-            libc::TCP_INFO,
-            &mut info as *mut _ as *mut libc::c_void,
-            &mut len,
-        )
-    };
+    // Corrected bandwidth and correction factors are computed at scrape time from the
+    // raw gauges; register those first so gathering never has to initialize them
+    lazy_static::initialize(&BANDWIDTH_GAUGE);
+    lazy_static::initialize(&BANDWIDTH_AVG_GAUGE);
+    lazy_static::initialize(&INTERFACE_BPS_GAUGE);
+    REGISTRY
+        .register(Box::new(derived::DerivedCollector::new()))
+        .unwrap();
 
-    if result == 0 && info.tcpi_rtt > 0 {
-        Some(Duration::from_micros(info.tcpi_rtt as u64))
-    } else {
-        None
+    // Start Prometheus metrics server (port 59121 by default)
+    {
+        let running = running.clone();
+        let listen = settings.metrics_listen;
+        rt.spawn(async move {
+            start_metrics_server(listen, running).await;
+        });
     }
-}
 
-#[cfg(not(target_os = "linux"))]
-fn get_tcp_info_rtt(_fd: i32) -> Option<Duration> {
-    // TCP_INFO is Linux-specific; return None on other platforms
-    None
-}
+    // Passive utilization from the interface counters
+    let sampler = Sampler::new(counter_plan(&settings));
+    rt.spawn(sampler.clone().run(running.clone()));
+
+    // Configuration reloads on SIGHUP and POST /reload
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    let reloader = Arc::new(Reloader {
+        config: args.config.clone(),
+        overrides,
+        listen: (settings.metrics_listen, settings.api_listen),
+        watching: settings.discovery.is_some(),
+        reloads: reload_tx,
+        current: Mutex::new(config),
+        links: Mutex::new(links),
+        discovered: Mutex::new(discovered),
+        sampler,
+    });
+    rt.spawn(reload_on_sighup(reloader.clone()));
+    if reloader.watching {
+        rt.spawn(rediscover_on_netlink(reloader.clone()));
+    }
 
-#[cfg(target_os = "linux")]
-fn bind_socket_to_interface(socket: &Socket, interface: &str) -> io::Result<()> {
-    // Use libc directly to set SO_BINDTODEVICE, since socket2 may not expose bind_device on all versions.
-    // Requires CAP_NET_RAW or root privileges on Linux.
-    let fd = socket.as_raw_fd();
-    let ifname = CString::new(interface)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Interface name contains NUL"))?;
+    // Start HTTP correction server (port 32600 by default)
+    {
+        let running = running.clone();
+        let listen = settings.api_listen;
+        rt.spawn(async move {
+            start_correction_server(listen, running, reloader).await;
+        });
+    }
 
-    let ret = unsafe {
-        libc::setsockopt(
-            fd,
-            libc::SOL_SOCKET,
-            libc::SO_BINDTODEVICE,
-            ifname.as_ptr() as *const libc::c_void,
-            ifname.as_bytes_with_nul().len() as libc::socklen_t,
-        )
+    println!(
+        "Prometheus metrics available at http://{}/metrics",
+        settings.metrics_listen
+    );
+    println!(
+        "Correction factor API available at http://{}/tcpflow?value=<factor>",
+        settings.api_listen
+    );
+    print_plan(&settings);
+    println!("Starting measurements...");
+    println!("==================================");
+
+    let schedule = Schedule {
+        report_interval: settings.report_interval,
+        max_concurrency: settings.max_concurrency,
     };
+    let probe: ProbeFn = Arc::new(probe_once);
+    let on_outcome: OutcomeFn = Arc::new(record_outcome);
+    let report: ReportFn = Arc::new(
+        |interface: &str, avg_bandwidth: Option<f64>, shares: &[(String, f64)]| {
+            match avg_bandwidth {
+                Some(avg_bandwidth) => BANDWIDTH_AVG_GAUGE
+                    .with_label_values(&[interface])
+                    .set(avg_bandwidth),
+                // Nothing answered on this interface; don't keep exporting the old average
+                None => {
+                    let _ = BANDWIDTH_AVG_GAUGE.remove_label_values(&[interface]);
+                    remove_series(&BANDWIDTH_AVG_SHARE_GAUGE, &[("interface", interface)]);
+                }
+            }
+            for (server, share) in shares {
+                BANDWIDTH_AVG_SHARE_GAUGE
+                    .with_label_values(&[interface, server])
+                    .set(*share);
+            }
+        },
+    );
 
-    if ret == 0 {
-        // Verify binding was successful by getting the bound interface
-        let mut buf = vec![0u8; libc::IFNAMSIZ];
-        let mut len = buf.len() as libc::socklen_t;
+    // Probe every pair concurrently until Ctrl+C
+    let scheduler = Scheduler::new(settings.targets, schedule, probe, on_outcome, report);
+    rt.block_on(scheduler.run(running, reload_rx));
 
-        let verify_ret = unsafe {
-            libc::getsockopt(
-                fd,
-                libc::SOL_SOCKET,
-                libc::SO_BINDTODEVICE,
-                buf.as_mut_ptr() as *mut libc::c_void,
-                &mut len,
-            )
+    println!("\nShutting down...");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn has_series(vec: &GaugeVec, labels: &[(&str, &str)]) -> bool {
+        vec.collect().iter().any(|family| {
+            family.get_metric().iter().any(|metric| {
+                labels.iter().all(|(name, value)| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == *name && label.get_value() == *value)
+                })
+            })
+        })
+    }
+
+    // A bulk probe against a server that never sends waits out its transfer duration. Past
+    // its probe_timeout, its result must not bring back the series the timeout removed.
+    #[test]
+    fn probe_past_its_timeout_exports_nothing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let overrides = CliOverrides {
+            interfaces: vec!["lo".to_string()],
+            servers: vec![server.clone()],
+            settings: ProbeSettings {
+                probe: Some(ProbeMode::Bulk),
+                duration: Some(1.0),
+                probe_timeout: Some(0.2),
+                interval: Some(60.0),
+                ..ProbeSettings::default()
+            },
+            ..CliOverrides::default()
+        };
+        let settings = Config::default().resolve(&overrides).unwrap();
+        let schedule = Schedule {
+            report_interval: settings.report_interval,
+            max_concurrency: 1,
         };
+        let scheduler = Scheduler::new(
+            settings.targets,
+            schedule,
+            Arc::new(probe_once),
+            Arc::new(record_outcome),
+            Arc::new(|_: &str, _: Option<f64>, _: &[(String, f64)]| {}),
+        );
 
-        if verify_ret == 0 && len > 0 {
-            // Find the first NUL byte to get the actual interface name length
-            let null_pos = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
-
-            // Convert to string, trimming at the first NUL
-            let bound_if = std::str::from_utf8(&buf[..null_pos]).unwrap_or("").trim();
-
-            if !bound_if.is_empty() && bound_if != interface {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Interface binding verification failed: expected {}, got {}",
-                        interface, bound_if
-                    ),
-                ));
-            }
-            // If bound_if is empty or matches, consider it successful
+        let running = Arc::new(AtomicBool::new(true));
+        let (_reloads, reload_rx) = mpsc::channel(1);
+        Runtime::new().unwrap().block_on(async {
+            let run = tokio::spawn(scheduler.run(running.clone(), reload_rx));
+            // Until well after the blocking probe has returned
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            running.store(false, Ordering::SeqCst);
+            run.await.unwrap();
+        });
+
+        assert_eq!(
+            PROBE_UP_GAUGE.with_label_values(&["lo", &server]).get(),
+            0.0
+        );
+        let labels = [("interface", "lo"), ("server_ip", "127.0.0.1")];
+        for gauge in target_gauges() {
+            assert!(!has_series(gauge, &labels), "{:?}", gauge.desc());
         }
+        drop(listener);
+    }
 
-        Ok(())
-    } else {
-        let err = io::Error::last_os_error();
-        // Provide more detailed error message
-        Err(io::Error::new(
-            err.kind(),
-            format!("Failed to bind to interface '{}': {}", interface, err),
-        ))
-    }
-}
-
-#[cfg(not(target_os = "linux"))]
-fn bind_socket_to_interface(_socket: &Socket, interface: &str) -> io::Result<()> {
-    // SO_BINDTODEVICE is not supported on non-Linux platforms.
-    // We can print a warning to the user.
-    // SO_BINDTODEVICE is not supported on non-Linux platforms.
-    // Print a one-time warning to the user to avoid spamming in the loop.
-    static ONCE: Once = Once::new();
-    ONCE.call_once(|| {
-            eprintln!(
-                "Warning: Binding to a specific interface ('{}') is only supported on Linux. This option will be ignored.",
-                interface
-            );
-        });
-    Ok(())
+    // A pair whose latest probe lacks a probe-specific value stops exporting the old one
+    #[test]
+    fn missing_values_drop_their_series() {
+        let overrides = CliOverrides {
+            interfaces: vec!["test0".to_string()],
+            servers: vec!["192.0.2.1".to_string()],
+            ..CliOverrides::default()
+        };
+        let settings = Config::default().resolve(&overrides).unwrap();
+        let target = &settings.targets[0];
+        let addr: SocketAddr = "192.0.2.1:80".parse().unwrap();
+        let mut measurement = Measurement {
+            rtt: Duration::from_millis(20),
+            connect_time: Duration::from_millis(20),
+            bound: true,
+            window_size: 65536,
+            tcp_info: None,
+            goodput: None,
+            http: None,
+            reflector: None,
+            responsiveness: Some(responsiveness::Responsiveness {
+                idle_rtt: Duration::from_millis(20),
+                loaded_rtt: Duration::from_millis(80),
+                loaded_samples: 10,
+                flows: 4,
+            }),
+        };
+        let labels = [("interface", "test0"), ("server_ip", "192.0.2.1")];
+
+        record_measurement(target, addr, &measurement, 1e6);
+        assert!(has_series(&RESPONSIVENESS_RPM_GAUGE, &labels));
+        assert!(has_series(&RESPONSIVENESS_RTT_GAUGE, &labels));
+
+        measurement.responsiveness = None;
+        record_measurement(target, addr, &measurement, 1e6);
+        assert!(!has_series(&RESPONSIVENESS_RPM_GAUGE, &labels));
+        assert!(!has_series(&RESPONSIVENESS_RTT_GAUGE, &labels));
+        assert!(has_series(&RTT_GAUGE, &labels));
+    }
 }
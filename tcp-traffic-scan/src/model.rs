@@ -0,0 +1,330 @@
+// Bandwidth models: different ways of turning one measurement into a bandwidth figure.
+// Every model that has the data it needs is exported for comparison; the configured one
+// is the bandwidth of the pair.
+use std::time::Duration;
+
+use crate::probe::{Measurement, ProbeMode};
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Model {
+    /// Socket buffer window divided by RTT, scaled by the efficiency factor
+    Buffer,
+    /// Sender congestion window times MSS divided by RTT
+    Cwnd,
+    /// Kernel delivery rate estimate of the sender (tcpi_delivery_rate)
+    DeliveryRate,
+    /// Measured steady-state goodput of a probe that transfers data
+    Goodput,
+    /// Buffer model capped by the Mathis bound MSS/RTT * C/sqrt(loss) of the sender.
+    /// Only the reflector probe measures loss, with other probes this equals buffer
+    Mathis,
+    /// Buffer model capped by the Padhye bound, which adds retransmission timeouts to Mathis.
+    /// Only the reflector probe measures loss, with other probes this equals buffer
+    Padhye,
+}
+
+// Constant of the Mathis formula for periodic loss with delayed ACKs off, sqrt(3/2)
+const MATHIS_C: f64 = 1.224_744_871;
+
+// Segments acknowledged by one ACK in the Padhye formula, 1 to match MATHIS_C
+const PADHYE_B: f64 = 1.0;
+
+// A loss rate from fewer data segments than this says nothing. In practice only the
+// reflector probe reaches it: the estimate probe sends no data, and a bulk, HTTP or
+// responsiveness download only sends its request from our side.
+const MIN_DATA_SEGMENTS: u32 = 10;
+
+impl Model {
+    pub const ALL: [Model; 6] = [
+        Model::Buffer,
+        Model::Cwnd,
+        Model::DeliveryRate,
+        Model::Goodput,
+        Model::Mathis,
+        Model::Padhye,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Model::Buffer => "buffer",
+            Model::Cwnd => "cwnd",
+            Model::DeliveryRate => "delivery_rate",
+            Model::Goodput => "goodput",
+            Model::Mathis => "mathis",
+            Model::Padhye => "padhye",
+        }
+    }
+
+    // Measured goodput for probes that transfer data, the buffer model for the estimate probe
+    pub fn default_for(probe: ProbeMode) -> Model {
+        match probe {
+            ProbeMode::Estimate => Model::Buffer,
+            _ => Model::Goodput,
+        }
+    }
+
+    // Bandwidth in bps, None when the measurement lacks what the model needs
+    pub fn estimate(self, measurement: &Measurement, tcp_efficiency: f64) -> Option<f64> {
+        match self {
+            Model::Buffer => buffer(measurement, tcp_efficiency),
+            Model::Cwnd => {
+                let sender = Sender::of(measurement)?;
+                per_rtt(sender.snd_cwnd as f64 * sender.mss as f64, sender.rtt)
+            }
+            Model::DeliveryRate => {
+                let sender = Sender::of(measurement)?;
+                (sender.delivery_rate > 0).then_some(sender.delivery_rate as f64 * 8.0)
+            }
+            Model::Goodput => measurement.goodput.as_ref().map(|goodput| goodput.bps()),
+            Model::Mathis | Model::Padhye => {
+                let buffer = buffer(measurement, tcp_efficiency)?;
+                let Some(sender) = Sender::of(measurement) else {
+                    return Some(buffer);
+                };
+                // Without loss, or without data to tell, loss does not limit the rate
+                let loss = match sender.loss_rate() {
+                    Some(loss) if loss > 0.0 => loss,
+                    _ => return Some(buffer),
+                };
+                let bound = match self {
+                    Model::Mathis => {
+                        per_rtt(sender.mss as f64 * MATHIS_C / loss.sqrt(), sender.rtt)?
+                    }
+                    _ => padhye(&sender, loss)?,
+                };
+                Some(buffer.min(bound))
+            }
+        }
+    }
+}
+
+// Share of its data segments the sender lost during the probe, None unless it sent enough
+// data to tell
+pub fn loss_rate(measurement: &Measurement) -> Option<f64> {
+    Sender::of(measurement)?.loss_rate()
+}
+
+// Padhye et al. steady-state throughput in bps for loss rate `p`: fast retransmits as in
+// Mathis plus the time spent in retransmission timeouts, which dominate at high loss
+fn padhye(sender: &Sender, p: f64) -> Option<f64> {
+    let rtt = sender.rtt.as_secs_f64();
+    if rtt <= 0.0 {
+        return None;
+    }
+    // Older reflectors don't report the RTO; Linux never goes below 200ms
+    let rto = match sender.rto.as_secs_f64() {
+        0.0 => (4.0 * rtt).max(0.2),
+        rto => rto,
+    };
+    let seconds_per_segment = rtt * (2.0 * PADHYE_B * p / 3.0).sqrt()
+        + rto * (3.0 * (3.0 * PADHYE_B * p / 8.0).sqrt()).min(1.0) * p * (1.0 + 32.0 * p * p);
+    Some(sender.mss as f64 * 8.0 / seconds_per_segment)
+}
+
+// Calculate bandwidth using improved formula
+// BDP (Bandwidth-Delay Product) = Bandwidth × RTT
+// Therefore: Bandwidth = Window Size / RTT
+//
+// We use a more conservative approach:
+// - Convert window size to bits (multiply by 8)
+// - Divide by RTT in seconds
+// - Apply a coefficient to account for TCP overhead and protocol efficiency
+fn buffer(measurement: &Measurement, tcp_efficiency: f64) -> Option<f64> {
+    // TCP typically achieves 70-90% efficiency due to:
+    // - ACK overhead
+    // - Retransmissions
+    // - Slow start and congestion control
+    per_rtt(measurement.window_size as f64, measurement.rtt).map(|bps| bps * tcp_efficiency)
+}
+
+// Bits per second of `bytes` sent once per `rtt`
+fn per_rtt(bytes: f64, rtt: Duration) -> Option<f64> {
+    let rtt_secs = rtt.as_secs_f64();
+    (rtt_secs > 0.0).then(|| bytes * 8.0 / rtt_secs)
+}
+
+// TCP state of the side that sent the data: the reflector for its downloads, our own
+// socket otherwise. For plain bulk and HTTP downloads our socket only sent ACKs, so the
+// sender models say little there.
+struct Sender {
+    rtt: Duration,
+    snd_cwnd: u32,
+    mss: u32,
+    delivery_rate: u64,
+    total_retrans: u32,
+    // Segments retransmitted and marked lost that are still unacknowledged
+    retrans: u32,
+    lost: u32,
+    data_segs_out: u32,
+    rto: Duration,
+}
+
+impl Sender {
+    fn of(measurement: &Measurement) -> Option<Sender> {
+        let info = measurement.tcp_info.as_ref();
+        let report = measurement
+            .reflector
+            .as_ref()
+            .and_then(|reflector| reflector.server.as_ref());
+        match report {
+            Some(report) if report.rtt_us > 0 => Some(Sender {
+                rtt: Duration::from_micros(report.rtt_us),
+                snd_cwnd: report.snd_cwnd,
+                // Reflectors before the MSS was reported: the path is the same both ways
+                mss: match report.snd_mss {
+                    0 => info?.rcv_mss,
+                    mss => mss,
+                },
+                delivery_rate: report.delivery_rate,
+                total_retrans: report.total_retrans,
+                retrans: report.retrans,
+                lost: report.lost,
+                data_segs_out: report.data_segs_out,
+                rto: Duration::from_micros(report.rto_us),
+            }),
+            _ => info.map(|info| Sender {
+                rtt: info.rtt,
+                snd_cwnd: info.snd_cwnd,
+                mss: info.snd_mss,
+                delivery_rate: info.delivery_rate,
+                total_retrans: info.total_retrans,
+                retrans: info.retrans,
+                lost: info.lost,
+                data_segs_out: info.data_segs_out,
+                rto: info.rto,
+            }),
+        }
+    }
+
+    // Every retransmission plus the segments marked lost that were not retransmitted yet
+    fn loss_rate(&self) -> Option<f64> {
+        if self.data_segs_out < MIN_DATA_SEGMENTS {
+            return None;
+        }
+        let lost = self.total_retrans as u64 + self.lost.saturating_sub(self.retrans) as u64;
+        Some((lost as f64 / self.data_segs_out as f64).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_info::TcpInfo;
+
+    // 1 MB window over 10ms: 800 Mbps by the buffer model at efficiency 1
+    fn measurement(data_segs_out: u32, total_retrans: u32) -> Measurement {
+        let rtt = Duration::from_millis(10);
+        Measurement {
+            rtt,
+            connect_time: rtt,
+            bound: true,
+            window_size: 1_000_000,
+            tcp_info: Some(TcpInfo {
+                rtt,
+                rto: Duration::from_millis(210),
+                snd_mss: 1000,
+                snd_cwnd: 10,
+                data_segs_out,
+                total_retrans,
+                ..Default::default()
+            }),
+            goodput: None,
+            http: None,
+            reflector: None,
+            responsiveness: None,
+        }
+    }
+
+    fn sender(data_segs_out: u32, total_retrans: u32, lost: u32, retrans: u32) -> Sender {
+        Sender {
+            rtt: Duration::from_millis(10),
+            snd_cwnd: 10,
+            mss: 1000,
+            delivery_rate: 0,
+            total_retrans,
+            retrans,
+            lost,
+            data_segs_out,
+            rto: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn loss_rate_needs_enough_data_segments() {
+        assert_eq!(sender(9, 1, 0, 0).loss_rate(), None);
+        assert_eq!(sender(10, 0, 0, 0).loss_rate(), Some(0.0));
+    }
+
+    #[test]
+    fn loss_rate_counts_lost_segments_not_yet_retransmitted() {
+        assert_eq!(sender(100, 2, 3, 1).loss_rate(), Some(0.04));
+        assert_eq!(sender(100, 2, 1, 3).loss_rate(), Some(0.02));
+        assert_eq!(sender(10, 20, 0, 0).loss_rate(), Some(1.0));
+    }
+
+    #[test]
+    fn loss_bounds_cap_the_buffer_model() {
+        let lossy = measurement(100, 1);
+        let buffer = Model::Buffer.estimate(&lossy, 1.0).unwrap();
+        let mathis = Model::Mathis.estimate(&lossy, 1.0).unwrap();
+        let padhye = Model::Padhye.estimate(&lossy, 1.0).unwrap();
+
+        assert_eq!(buffer, 800e6);
+        // MSS * C / sqrt(0.01) per 10ms
+        assert!((mathis - 1000.0 * MATHIS_C * 10.0 * 8.0 / 0.01).abs() < 1.0);
+        // Timeouts only make it slower
+        assert!(padhye < mathis);
+    }
+
+    #[test]
+    fn loss_bounds_never_raise_the_buffer_model() {
+        // A high loss bound above the buffer model leaves it as it is
+        let lossy = measurement(100, 1);
+        let tiny_window = Measurement {
+            window_size: 1000,
+            ..lossy
+        };
+        let buffer = Model::Buffer.estimate(&tiny_window, 1.0);
+
+        assert_eq!(Model::Mathis.estimate(&tiny_window, 1.0), buffer);
+        assert_eq!(Model::Padhye.estimate(&tiny_window, 1.0), buffer);
+    }
+
+    #[test]
+    fn loss_bounds_equal_the_buffer_model_without_loss_data() {
+        for clean in [measurement(100, 0), measurement(5, 1)] {
+            let buffer = Model::Buffer.estimate(&clean, 1.0);
+
+            assert_eq!(Model::Mathis.estimate(&clean, 1.0), buffer);
+            assert_eq!(Model::Padhye.estimate(&clean, 1.0), buffer);
+        }
+    }
+
+    #[test]
+    fn padhye_falls_back_to_a_200ms_rto() {
+        let without_rto = sender(100, 10, 0, 0);
+        let with_rto = Sender {
+            rto: Duration::from_millis(200),
+            ..sender(100, 10, 0, 0)
+        };
+
+        assert_eq!(padhye(&without_rto, 0.1), padhye(&with_rto, 0.1));
+        assert_eq!(
+            padhye(
+                &Sender {
+                    rtt: Duration::ZERO,
+                    ..without_rto
+                },
+                0.1
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn goodput_model_needs_a_transfer() {
+        assert_eq!(Model::Goodput.estimate(&measurement(100, 0), 1.0), None);
+    }
+}
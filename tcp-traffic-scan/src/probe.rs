@@ -0,0 +1,784 @@
+use socket2::{Domain, Socket, Type};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use crate::http_probe::HttpTiming;
+use crate::reflector::ReflectorStats;
+use crate::responsiveness::Responsiveness;
+use crate::tcp_info::{get_tcp_info, TcpInfo};
+
+// Why a probe failed, exported as the `reason` label of the failure counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    Dns,
+    ConnectTimeout,
+    Refused,
+    Bind,
+    Getsockopt,
+    // The interface has no carrier or is down; the probe was skipped
+    LinkDown,
+    // The probe exceeded its deadline as a whole
+    Timeout,
+    // The probe did not provide what the configured bandwidth model needs
+    NoEstimate,
+    Other,
+}
+
+impl FailureReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureReason::Dns => "dns",
+            FailureReason::ConnectTimeout => "connect_timeout",
+            FailureReason::Refused => "refused",
+            FailureReason::Bind => "bind",
+            FailureReason::Getsockopt => "getsockopt",
+            FailureReason::LinkDown => "link_down",
+            FailureReason::Timeout => "timeout",
+            FailureReason::NoEstimate => "no_estimate",
+            FailureReason::Other => "other",
+        }
+    }
+
+    // Reason attached with `fail`, otherwise guessed from the error kind
+    pub fn of(e: &io::Error) -> FailureReason {
+        if let Some(tagged) = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<TaggedError>())
+        {
+            return tagged.reason;
+        }
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => FailureReason::Refused,
+            _ => FailureReason::Other,
+        }
+    }
+}
+
+// io::Error that remembers which step of the probe produced it
+#[derive(Debug)]
+struct TaggedError {
+    reason: FailureReason,
+    source: io::Error,
+}
+
+impl fmt::Display for TaggedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for TaggedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Tag `e` with `reason`; kind and message stay the same
+pub fn fail(reason: FailureReason, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), TaggedError { reason, source: e })
+}
+
+pub fn resolve_server_address(server_str: &str) -> io::Result<SocketAddr> {
+    // Append a default port if not specified, required by ToSocketAddrs
+    let addr_with_port = if server_str.contains(':') {
+        server_str.to_string()
+    } else {
+        format!("{}:443", server_str) // Default to port 443 for resolution
+    };
+
+    addr_with_port
+        .to_socket_addrs()
+        .map_err(|e| fail(FailureReason::Dns, e))?
+        .next()
+        .ok_or_else(|| {
+            fail(
+                FailureReason::Dns,
+                io::Error::new(io::ErrorKind::NotFound, "Could not resolve address"),
+            )
+        })
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeMode {
+    /// SO_RCVBUF/SO_SNDBUF divided by RTT, no payload is transferred
+    Estimate,
+    /// Download from the server and measure real goodput
+    Bulk,
+    /// Issue an HTTP(S) GET for the configured URL and measure TTFB, TLS handshake and transfer
+    Http,
+    /// Echo and download against a `tcp-traffic-scan serve` reflector
+    Reflector,
+    /// Handshake RTT idle and while parallel downloads saturate the link (bufferbloat)
+    Responsiveness,
+}
+
+// Result of a single probe against one server
+pub struct Measurement {
+    pub rtt: Duration,
+    // TCP handshake duration as seen by connect()
+    pub connect_time: Duration,
+    // Whether the socket was bound to the interface; false means the kernel picked the route
+    pub bound: bool,
+    pub window_size: u32,
+    // Full kernel TCP_INFO snapshot (Linux only), taken after the handshake
+    // or, for bulk probes, after the transfer finished
+    pub tcp_info: Option<TcpInfo>,
+    // Measured goodput when the probe actually transferred data
+    pub goodput: Option<Goodput>,
+    // Request timings for HTTP(S) probes
+    pub http: Option<HttpTiming>,
+    // Echo RTT and server-side report from a reflector probe
+    pub reflector: Option<ReflectorStats>,
+    // Idle and loaded RTT from a responsiveness probe
+    pub responsiveness: Option<Responsiveness>,
+}
+
+// Bytes moved during one phase of a bulk transfer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Phase {
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl Phase {
+    pub fn bps(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 * 8.0 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+// Goodput measured by a bulk transfer, split into slow-start ramp-up and steady state
+#[derive(Debug, Clone, Copy)]
+pub struct Goodput {
+    pub total: Phase,
+    pub ramp_up: Phase,
+    pub steady: Phase,
+}
+
+impl Goodput {
+    // Steady-state rate, or the overall rate if the transfer never left ramp-up
+    pub fn bps(&self) -> f64 {
+        if self.steady.bytes > 0 {
+            self.steady.bps()
+        } else {
+            self.total.bps()
+        }
+    }
+}
+
+// Parameters shared by all probe types
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeOptions {
+    // Give up on the TCP handshake after this long
+    pub connect_timeout: Duration,
+    // Stop after this many bytes have been received
+    pub bytes: u64,
+    // Stop after this much time, whichever comes first
+    pub duration: Duration,
+    // Optional payload written right after connecting (e.g. a request line)
+    pub request: Option<Vec<u8>>,
+    // Fail instead of probing over the default route when the device binding fails
+    pub strict_bind: bool,
+    // Parallel downloads of the responsiveness probe
+    pub flows: usize,
+    pub route: Route,
+}
+
+// How a probe socket is pinned to the path of its interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    // SO_BINDTODEVICE to the interface
+    pub bind_device: bool,
+    // SO_MARK so `ip rule fwmark` policy routing applies, as for customer traffic
+    pub fwmark: Option<u32>,
+    // Local address bound before connecting
+    pub source: Option<IpAddr>,
+    // Network namespace the socket is created in
+    pub netns: Option<PathBuf>,
+    // VRF device bound instead of the interface
+    pub vrf: Option<String>,
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Route {
+            bind_device: true,
+            fwmark: None,
+            source: None,
+            netns: None,
+            vrf: None,
+        }
+    }
+}
+
+// Width of the buckets used to detect the end of ramp-up
+const GOODPUT_BUCKET: Duration = Duration::from_millis(100);
+
+// Connected probe socket
+pub struct Connection {
+    pub socket: Socket,
+    // Time taken by the handshake
+    pub connect_time: Duration,
+    // Whether the socket is pinned to the interface by device binding, fwmark or source address
+    pub bound: bool,
+}
+
+// Create a socket bound to `interface` and connect it to `addr`.
+// A configured fwmark or source address must apply. Unless `options.strict_bind` is set,
+// a failed device binding only warns and the kernel picks the route.
+pub fn connect_bound(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<Connection> {
+    let domain = if addr.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    };
+
+    let route = &options.route;
+    let socket = match &route.netns {
+        Some(netns) => socket_in_netns(netns, domain)?,
+        None => Socket::new(domain, Type::STREAM, None)?,
+    };
+
+    // Set socket options before connecting for better control
+    socket.set_nodelay(true)?; // Disable Nagle's algorithm for faster response
+    socket.set_keepalive(true)?; // Enable keepalive to maintain connection state
+
+    // Bind the socket to the specified interface (Linux-only), or to its VRF
+    // so the VRF routing table applies. Inside a namespace the namespace picks the route.
+    let mut bound = route.netns.is_some();
+    if route.bind_device {
+        let device = route.vrf.as_deref().unwrap_or(interface);
+        bound = match bind_socket_to_interface(&socket, device) {
+            Ok(bound) => bound,
+            // A missing device is a misconfiguration rather than missing privileges; its
+            // probes would only measure the default route, so they fail even when not strict
+            Err(e) if options.strict_bind || FailureReason::of(&e) == FailureReason::Bind => {
+                return Err(fail(FailureReason::Bind, e));
+            }
+            Err(e) => {
+                eprintln!(
+                    "Warning: Failed to bind to device '{}'. This might require root privileges. Error: {}",
+                    device, e
+                );
+                // Continue without binding, the OS will choose the interface.
+                bound
+            }
+        };
+    }
+    if let Some(mark) = route.fwmark {
+        set_mark(&socket, mark).map_err(|e| {
+            let message = format!("Failed to set fwmark {:#x}: {}", mark, e);
+            fail(FailureReason::Bind, io::Error::new(e.kind(), message))
+        })?;
+        bound = true;
+    }
+    if let Some(source) = route.source {
+        socket
+            .bind(&SocketAddr::new(source, 0).into())
+            .map_err(|e| {
+                let message = format!("Failed to bind to source address {}: {}", source, e);
+                fail(FailureReason::Bind, io::Error::new(e.kind(), message))
+            })?;
+        bound = true;
+    }
+    if !bound && options.strict_bind {
+        return Err(fail(
+            FailureReason::Bind,
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Cannot bind to '{}' on this platform", interface),
+            ),
+        ));
+    }
+
+    // Measure connection establishment time (includes SYN, SYN-ACK, ACK)
+    let start = Instant::now();
+    socket
+        .connect_timeout(&addr.into(), options.connect_timeout)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                fail(FailureReason::ConnectTimeout, e)
+            }
+            io::ErrorKind::ConnectionRefused => fail(FailureReason::Refused, e),
+            _ => e,
+        })?;
+    let connect_time = start.elapsed();
+
+    Ok(Connection {
+        socket,
+        connect_time,
+        bound,
+    })
+}
+
+pub fn measure_throughput(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<Measurement> {
+    let Connection {
+        socket,
+        connect_time,
+        bound,
+    } = connect_bound(interface, addr, options)?;
+
+    // RTT is approximately half of the connection time (SYN -> SYN-ACK)
+    // This is more accurate than using the full connect_timeout duration
+    let estimated_rtt = connect_time / 2;
+
+    let fd = socket.as_raw_fd();
+
+    // Wait a bit to let TCP connection fully establish and negotiate window
+    std::thread::sleep(Duration::from_millis(10));
+
+    // Get TCP_INFO for more accurate RTT measurement
+    let tcp_info = get_tcp_info(fd);
+    let actual_rtt = tcp_rtt(&tcp_info).unwrap_or(estimated_rtt);
+
+    let effective_window = effective_window(fd)?;
+
+    Ok(Measurement {
+        rtt: actual_rtt,
+        connect_time,
+        bound,
+        window_size: effective_window,
+        tcp_info,
+        goodput: None,
+        http: None,
+        reflector: None,
+        responsiveness: None,
+    })
+}
+
+// Active probe: download up to `options.bytes` (or for `options.duration`) and time it
+pub fn measure_goodput(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<Measurement> {
+    let Connection {
+        socket,
+        connect_time,
+        bound,
+    } = connect_bound(interface, addr, options)?;
+    let fd = socket.as_raw_fd();
+    let mut stream: std::net::TcpStream = socket.into();
+
+    // Never block longer than the whole probe is allowed to take
+    stream.set_read_timeout(Some(options.duration))?;
+
+    if let Some(request) = &options.request {
+        stream.write_all(request)?;
+    }
+
+    let goodput = receive_bulk(&mut stream, options.bytes, options.duration)?;
+    if goodput.total.bytes == 0 {
+        return Err(no_data(&goodput, options.duration));
+    }
+
+    // Snapshot after the transfer so cwnd, delivery rate and retransmits reflect real load
+    let tcp_info = get_tcp_info(fd);
+    let rtt = tcp_rtt(&tcp_info).unwrap_or(connect_time / 2);
+    let window_size = effective_window(fd)?;
+
+    Ok(Measurement {
+        rtt,
+        connect_time,
+        bound,
+        window_size,
+        tcp_info,
+        goodput: Some(goodput),
+        http: None,
+        reflector: None,
+        responsiveness: None,
+    })
+}
+
+// Error for a transfer that received nothing: the read timeout ends it at `max_duration`,
+// anything shorter means the server closed the connection
+pub fn no_data(goodput: &Goodput, max_duration: Duration) -> io::Error {
+    if goodput.total.duration >= max_duration {
+        fail(
+            FailureReason::Timeout,
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Server sent no data within {:?}", max_duration),
+            ),
+        )
+    } else {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Server closed the connection without sending data",
+        )
+    }
+}
+
+// Read from `reader` until `max_bytes`, `max_duration` or EOF and split the
+// transfer into ramp-up and steady-state phases
+pub fn receive_bulk<R: Read>(
+    reader: &mut R,
+    max_bytes: u64,
+    max_duration: Duration,
+) -> io::Result<Goodput> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut buckets: Vec<u64> = Vec::new();
+    let mut total: u64 = 0;
+    let start = Instant::now();
+
+    while total < max_bytes && start.elapsed() < max_duration {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        };
+
+        let bucket = (start.elapsed().as_nanos() / GOODPUT_BUCKET.as_nanos()) as usize;
+        if buckets.len() <= bucket {
+            buckets.resize(bucket + 1, 0);
+        }
+        buckets[bucket] += n as u64;
+        total += n as u64;
+    }
+
+    let elapsed = start.elapsed();
+    Ok(split_phases(&buckets, total, elapsed))
+}
+
+// Ramp-up ends at the first bucket that reaches 80% of the median rate of the
+// second half of the transfer. Everything from there on counts as steady state.
+fn split_phases(buckets: &[u64], total: u64, elapsed: Duration) -> Goodput {
+    let total_phase = Phase {
+        bytes: total,
+        duration: elapsed,
+    };
+
+    // The last bucket is usually partial, leave it out of the reference rate
+    let full = buckets.len().saturating_sub(1);
+    if full < 2 {
+        return Goodput {
+            total: total_phase,
+            ramp_up: Phase::default(),
+            steady: total_phase,
+        };
+    }
+
+    let mut tail: Vec<u64> = buckets[full / 2..full].to_vec();
+    tail.sort_unstable();
+    let reference = tail[tail.len() / 2];
+    let threshold = reference as f64 * 0.8;
+
+    let ramp_buckets = buckets
+        .iter()
+        .position(|&b| b as f64 >= threshold)
+        .unwrap_or(0);
+
+    let ramp_bytes: u64 = buckets[..ramp_buckets].iter().sum();
+    let ramp_duration = (GOODPUT_BUCKET * ramp_buckets as u32).min(elapsed);
+
+    Goodput {
+        total: total_phase,
+        ramp_up: Phase {
+            bytes: ramp_bytes,
+            duration: ramp_duration,
+        },
+        steady: Phase {
+            bytes: total - ramp_bytes,
+            duration: elapsed - ramp_duration,
+        },
+    }
+}
+
+pub fn tcp_rtt(tcp_info: &Option<TcpInfo>) -> Option<Duration> {
+    tcp_info
+        .as_ref()
+        .map(|info| info.rtt)
+        .filter(|rtt| !rtt.is_zero())
+}
+
+// min(SO_RCVBUF, SO_SNDBUF) as the effective TCP window
+pub fn effective_window(fd: i32) -> io::Result<u32> {
+    // Get receive buffer size (SO_RCVBUF)
+    let mut rcv_buf: libc::c_int = 0;
+    let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &mut rcv_buf as *mut _ as *mut libc::c_void,
+            &mut optlen,
+        )
+    };
+
+    if result != 0 {
+        return Err(fail(FailureReason::Getsockopt, io::Error::last_os_error()));
+    }
+
+    // Linux doubles the returned value for internal bookkeeping
+    #[cfg(target_os = "linux")]
+    let actual_rcv_buf = (rcv_buf / 2) as u32;
+
+    #[cfg(not(target_os = "linux"))]
+    let actual_rcv_buf = rcv_buf as u32;
+
+    // Get send buffer size (SO_SNDBUF) as well for better estimation
+    let mut snd_buf: libc::c_int = 0;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            &mut snd_buf as *mut _ as *mut libc::c_void,
+            &mut optlen,
+        )
+    };
+
+    let actual_snd_buf = if result == 0 {
+        #[cfg(target_os = "linux")]
+        let buf = (snd_buf / 2) as u32;
+        #[cfg(not(target_os = "linux"))]
+        let buf = snd_buf as u32;
+        buf
+    } else {
+        actual_rcv_buf // Fallback to receive buffer
+    };
+
+    // Use the minimum of send and receive buffer as the effective window
+    // This better represents the actual TCP window limitation
+    Ok(std::cmp::min(actual_rcv_buf, actual_snd_buf))
+}
+
+// Returns whether the socket is now bound to `interface`
+#[cfg(target_os = "linux")]
+fn bind_socket_to_interface(socket: &Socket, interface: &str) -> io::Result<bool> {
+    // Use libc directly to set SO_BINDTODEVICE, since socket2 may not expose bind_device on all versions.
+    // Requires CAP_NET_RAW or root privileges on Linux.
+    let fd = socket.as_raw_fd();
+    let ifname = CString::new(interface)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Interface name contains NUL"))?;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            ifname.as_ptr() as *const libc::c_void,
+            ifname.as_bytes_with_nul().len() as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        // Verify binding was successful by getting the bound interface
+        let mut buf = vec![0u8; libc::IFNAMSIZ];
+        let mut len = buf.len() as libc::socklen_t;
+
+        let verify_ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        if verify_ret == 0 && len > 0 {
+            // Find the first NUL byte to get the actual interface name length
+            let null_pos = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+
+            // Convert to string, trimming at the first NUL
+            let bound_if = std::str::from_utf8(&buf[..null_pos]).unwrap_or("").trim();
+
+            if !bound_if.is_empty() && bound_if != interface {
+                return Err(io::Error::other(format!(
+                    "Interface binding verification failed: expected {}, got {}",
+                    interface, bound_if
+                )));
+            }
+            // If bound_if is empty or matches, consider it successful
+        }
+
+        Ok(true)
+    } else {
+        let err = io::Error::last_os_error();
+        let missing = err.raw_os_error() == Some(libc::ENODEV);
+        // Provide more detailed error message
+        let err = io::Error::new(
+            err.kind(),
+            format!("Failed to bind to interface '{}': {}", interface, err),
+        );
+        // Without the device the probe would silently measure the default route instead
+        if missing {
+            Err(fail(FailureReason::Bind, err))
+        } else {
+            Err(err)
+        }
+    }
+}
+
+// Sockets stay in the namespace they were created in, so only the creation runs on a
+// short-lived thread that enters `netns`; the probe threads never switch namespaces.
+// setns needs CAP_SYS_ADMIN. Failing to enter the namespace is a Bind failure, the probe
+// never falls back to the host namespace.
+#[cfg(target_os = "linux")]
+fn socket_in_netns(netns: &Path, domain: Domain) -> io::Result<Socket> {
+    let path = netns.to_path_buf();
+    let created = std::thread::spawn(move || {
+        let ns = std::fs::File::open(&path)?;
+        if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Socket::new(domain, Type::STREAM, None)
+    })
+    .join()
+    .unwrap_or_else(|_| Err(io::Error::other("namespace thread panicked")));
+    created.map_err(|e| {
+        let message = format!(
+            "Failed to enter network namespace {}: {}",
+            netns.display(),
+            e
+        );
+        fail(FailureReason::Bind, io::Error::new(e.kind(), message))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_in_netns(netns: &Path, _domain: Domain) -> io::Result<Socket> {
+    Err(fail(
+        FailureReason::Bind,
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Network namespaces are only supported on Linux ({})",
+                netns.display()
+            ),
+        ),
+    ))
+}
+
+// Operational state of `device` from sysfs: Some(false) when it is down or has no carrier,
+// None when it cannot be told (no such device in this namespace)
+#[cfg(target_os = "linux")]
+pub fn link_up(device: &str) -> Option<bool> {
+    let dir = Path::new("/sys/class/net").join(device);
+    let operstate = std::fs::read_to_string(dir.join("operstate")).ok()?;
+    match operstate.trim() {
+        "up" => Some(true),
+        // Drivers that don't track the operational state (loopback, some tun/ppp)
+        "unknown" => match std::fs::read_to_string(dir.join("carrier")) {
+            Ok(carrier) => Some(carrier.trim() == "1"),
+            // carrier cannot be read while the link is administratively down
+            Err(_) => Some(false),
+        },
+        _ => Some(false),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn link_up(_device: &str) -> Option<bool> {
+    None
+}
+
+// SO_MARK needs CAP_NET_ADMIN
+#[cfg(target_os = "linux")]
+fn set_mark(socket: &Socket, mark: u32) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_mark(_socket: &Socket, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_MARK is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_socket_to_interface(_socket: &Socket, interface: &str) -> io::Result<bool> {
+    // SO_BINDTODEVICE is not supported on non-Linux platforms.
+    // We can print a warning to the user.
+    // SO_BINDTODEVICE is not supported on non-Linux platforms.
+    // Print a one-time warning to the user to avoid spamming in the loop.
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+            eprintln!(
+                "Warning: Binding to a specific interface ('{}') is only supported on Linux. This option will be ignored.",
+                interface
+            );
+        });
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramp_up_ends_at_the_first_bucket_near_the_steady_rate() {
+        let buckets = [100, 300, 1000, 900, 1000, 1000, 1000, 400];
+        let total = buckets.iter().sum();
+        let goodput = split_phases(&buckets, total, Duration::from_millis(740));
+
+        assert_eq!(goodput.total.bytes, total);
+        assert_eq!(goodput.ramp_up.bytes, 400);
+        assert_eq!(goodput.ramp_up.duration, Duration::from_millis(200));
+        assert_eq!(goodput.steady.bytes, total - 400);
+        assert_eq!(goodput.steady.duration, Duration::from_millis(540));
+    }
+
+    #[test]
+    fn short_transfer_is_all_steady_state() {
+        let goodput = split_phases(&[500, 200], 700, Duration::from_millis(150));
+
+        assert_eq!(goodput.ramp_up.bytes, 0);
+        assert_eq!(goodput.steady.bytes, 700);
+        assert_eq!(goodput.steady.duration, Duration::from_millis(150));
+    }
+
+    #[test]
+    fn bulk_receive_counts_every_byte_up_to_eof() {
+        let mut reader = io::Cursor::new(vec![0u8; 200 * 1024]);
+        let goodput = receive_bulk(&mut reader, u64::MAX, Duration::from_secs(5)).unwrap();
+
+        assert_eq!(goodput.total.bytes, 200 * 1024);
+        assert_eq!(goodput.ramp_up.bytes + goodput.steady.bytes, 200 * 1024);
+    }
+}
@@ -0,0 +1,431 @@
+// Reflector protocol shared by `tcp-traffic-scan serve` and the `reflector` probe.
+//
+// The client sends one JSON request per line and may send several on one connection:
+//   {"mode":"echo","count":5}
+//     -> the client sends `count` 8-byte sequence numbers, the server answers each
+//        with the sequence number followed by its own 8-byte timestamp (µs since epoch)
+//   {"mode":"download","bytes":N,"duration_ms":M}
+//     -> the server answers with a JSON header line, then frames of
+//        `u32 length (big endian) + payload` until N bytes or M ms are reached,
+//        then a zero-length frame and a JSON trailer line with server-side counters
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+use crate::probe::{
+    connect_bound, effective_window, receive_bulk, tcp_rtt, Connection, Measurement, ProbeOptions,
+};
+use crate::tcp_info::get_tcp_info;
+
+pub const DEFAULT_PORT: u16 = 59123;
+
+// Size of one data frame sent by the server
+const FRAME_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum Request {
+    Echo { count: u32 },
+    Download { bytes: u64, duration_ms: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DownloadHeader {
+    // Server clock when the transfer started
+    start_us: u64,
+}
+
+// Server-side view of one download, sent after the last frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerReport {
+    pub start_us: u64,
+    pub end_us: u64,
+    pub bytes_sent: u64,
+    // Sender-side TCP_INFO at the end of the transfer (0 when unavailable)
+    pub rtt_us: u64,
+    pub snd_cwnd: u32,
+    pub total_retrans: u32,
+    pub delivery_rate: u64,
+    // Missing from reflectors of older versions
+    #[serde(default)]
+    pub snd_mss: u32,
+    #[serde(default)]
+    pub data_segs_out: u32,
+    #[serde(default)]
+    pub retrans: u32,
+    #[serde(default)]
+    pub lost: u32,
+    #[serde(default)]
+    pub rto_us: u64,
+    // Lifetime counters of the reflector process
+    pub connections_total: u64,
+    pub bytes_sent_total: u64,
+}
+
+impl ServerReport {
+    // Send rate as seen by the reflector
+    pub fn send_bps(&self) -> f64 {
+        let secs = self.end_us.saturating_sub(self.start_us) as f64 / 1_000_000.0;
+        if secs > 0.0 {
+            self.bytes_sent as f64 * 8.0 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+// Result of the reflector probe in addition to the common measurement
+#[derive(Debug, Clone)]
+pub struct ReflectorStats {
+    // Median application-level round trip of the echo phase
+    pub echo_rtt: Option<Duration>,
+    pub server: Option<ServerReport>,
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+// Limits enforced by the reflector regardless of what clients ask for
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    pub max_bytes: u64,
+    pub max_duration: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    connections: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+// Run the reflector until `running` is cleared
+pub async fn serve(options: ServeOptions, running: Arc<AtomicBool>) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(options.listen).await?;
+    let options = Arc::new(options);
+    let counters = Arc::new(Counters::default());
+
+    while running.load(Ordering::SeqCst) {
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        let (stream, peer) = match accepted {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                eprintln!("Reflector accept failed: {}", e);
+                continue;
+            }
+            Err(_) => continue, // Timed out, re-check `running`
+        };
+
+        counters.connections.fetch_add(1, Ordering::Relaxed);
+        let options = options.clone();
+        let counters = counters.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &options, &counters).await {
+                eprintln!("Reflector connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    options: &ServeOptions,
+    counters: &Counters,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let fd = stream.as_raw_fd();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let request: Request = serde_json::from_str(line.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        match request {
+            Request::Echo { count } => {
+                let mut seq = [0u8; 8];
+                for _ in 0..count {
+                    stream.read_exact(&mut seq).await?;
+                    let mut reply = [0u8; 16];
+                    reply[..8].copy_from_slice(&seq);
+                    reply[8..].copy_from_slice(&now_us().to_be_bytes());
+                    stream.get_mut().write_all(&reply).await?;
+                }
+            }
+            Request::Download { bytes, duration_ms } => {
+                let bytes = bytes.min(options.max_bytes);
+                let duration = Duration::from_millis(duration_ms).min(options.max_duration);
+
+                let start_us = now_us();
+                let header = serde_json::to_string(&DownloadHeader { start_us })?;
+                let writer = stream.get_mut();
+                writer.write_all(header.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+
+                // Length prefix and payload go out in one write to avoid tiny segments
+                let mut frame = vec![0u8; 4 + FRAME_SIZE];
+                let start = Instant::now();
+                let mut sent: u64 = 0;
+                while sent < bytes && start.elapsed() < duration {
+                    let len = (bytes - sent).min(FRAME_SIZE as u64) as usize;
+                    frame[..4].copy_from_slice(&(len as u32).to_be_bytes());
+                    writer.write_all(&frame[..4 + len]).await?;
+                    sent += len as u64;
+                }
+                writer.write_all(&0u32.to_be_bytes()).await?;
+
+                let bytes_sent_total =
+                    counters.bytes_sent.fetch_add(sent, Ordering::Relaxed) + sent;
+                let info = get_tcp_info(fd).unwrap_or_default();
+                let report = ServerReport {
+                    start_us,
+                    end_us: now_us(),
+                    bytes_sent: sent,
+                    rtt_us: info.rtt.as_micros() as u64,
+                    snd_cwnd: info.snd_cwnd,
+                    total_retrans: info.total_retrans,
+                    delivery_rate: info.delivery_rate,
+                    snd_mss: info.snd_mss,
+                    data_segs_out: info.data_segs_out,
+                    retrans: info.retrans,
+                    lost: info.lost,
+                    rto_us: info.rto.as_micros() as u64,
+                    connections_total: counters.connections.load(Ordering::Relaxed),
+                    bytes_sent_total,
+                };
+                let trailer = serde_json::to_string(&report)?;
+                writer.write_all(trailer.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+        }
+    }
+}
+
+// Strips the `u32 length + payload` framing and reports EOF at the zero-length frame
+struct FrameReader<'a, R: BufRead> {
+    inner: &'a mut R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Read for FrameReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut len = [0u8; 4];
+            self.inner.read_exact(&mut len)?;
+            self.remaining = u32::from_be_bytes(len) as usize;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let want = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Reflector closed the connection mid-frame",
+            ));
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+// Number of echo round trips before the download
+const ECHO_COUNT: u32 = 5;
+
+// Time allowed past the deadline for the frames still queued towards us, so the trailer
+// with the server report is read on links limited by their own capacity too
+const TRAILER_GRACE: Duration = Duration::from_secs(2);
+
+// Probe a reflector: a short echo phase for application RTT, then a framed download
+pub fn measure_reflector(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<Measurement> {
+    let Connection {
+        socket,
+        connect_time,
+        bound,
+    } = connect_bound(interface, addr, options)?;
+    let fd = socket.as_raw_fd();
+    let stream: std::net::TcpStream = socket.into();
+    stream.set_read_timeout(Some(options.duration))?;
+    stream.set_write_timeout(Some(options.duration))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    // Echo phase
+    send_request(&mut writer, &Request::Echo { count: ECHO_COUNT })?;
+    let mut rtts = Vec::with_capacity(ECHO_COUNT as usize);
+    for seq in 0..ECHO_COUNT as u64 {
+        let start = Instant::now();
+        writer.write_all(&seq.to_be_bytes())?;
+        let mut reply = [0u8; 16];
+        reader.read_exact(&mut reply)?;
+        if reply[..8] != seq.to_be_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reflector echoed an unexpected sequence number",
+            ));
+        }
+        rtts.push(start.elapsed());
+    }
+    rtts.sort_unstable();
+    let echo_rtt = rtts.get(rtts.len() / 2).copied();
+
+    // Download phase
+    send_request(
+        &mut writer,
+        &Request::Download {
+            bytes: options.bytes,
+            duration_ms: options.duration.as_millis() as u64,
+        },
+    )?;
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    serde_json::from_str::<DownloadHeader>(header.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut frames = FrameReader {
+        inner: &mut reader,
+        remaining: 0,
+        done: false,
+    };
+    let goodput = receive_bulk(&mut frames, u64::MAX, options.duration)?;
+    // The server stops at the same deadline, but up to a send buffer of frames is still on
+    // its way; read them without counting them to get to the trailer
+    drain_frames(&mut frames, TRAILER_GRACE);
+
+    // The trailer is only there when the server finished the transfer on its own
+    let server = if frames.done {
+        reader.get_ref().set_read_timeout(Some(TRAILER_GRACE))?;
+        let mut trailer = String::new();
+        reader.read_line(&mut trailer)?;
+        serde_json::from_str::<ServerReport>(trailer.trim()).ok()
+    } else {
+        None
+    };
+
+    let tcp_info = get_tcp_info(fd);
+    let window_size = effective_window(fd)?;
+
+    Ok(Measurement {
+        rtt: tcp_rtt(&tcp_info).unwrap_or(connect_time / 2),
+        connect_time,
+        bound,
+        window_size,
+        tcp_info,
+        goodput: Some(goodput),
+        http: None,
+        reflector: Some(ReflectorStats { echo_rtt, server }),
+        responsiveness: None,
+    })
+}
+
+// Discard frames until the zero-length one or until `grace` has passed
+fn drain_frames(frames: &mut FrameReader<'_, BufReader<std::net::TcpStream>>, grace: Duration) {
+    let deadline = Instant::now() + grace;
+    let mut buf = vec![0u8; FRAME_SIZE];
+    while !frames.done {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || frames.inner.get_ref().set_read_timeout(Some(left)).is_err() {
+            return;
+        }
+        match frames.read(&mut buf) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return,
+        }
+    }
+}
+
+fn send_request<W: Write>(writer: &mut W, request: &Request) -> io::Result<()> {
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(frames: &[&[u8]], after: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for frame in frames {
+            bytes.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(frame);
+        }
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(after);
+        bytes
+    }
+
+    #[test]
+    fn frames_are_joined_up_to_the_end_marker() {
+        let bytes = framed(&[b"abc", b"defgh"], b"{\"trailer\":1}\n");
+        let mut inner = io::Cursor::new(bytes);
+        let mut frames = FrameReader {
+            inner: &mut inner,
+            remaining: 0,
+            done: false,
+        };
+
+        // Reads smaller than a frame must not lose the frame boundary
+        let mut payload = Vec::new();
+        let mut buf = [0u8; 2];
+        loop {
+            match frames.read(&mut buf).unwrap() {
+                0 => break,
+                n => payload.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(payload, b"abcdefgh");
+        assert_eq!(frames.read(&mut buf).unwrap(), 0);
+
+        // What follows the end marker is left for the trailer
+        let mut trailer = String::new();
+        BufRead::read_line(&mut inner, &mut trailer).unwrap();
+        assert_eq!(trailer, "{\"trailer\":1}\n");
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let mut bytes = framed(&[b"abcdef"], b"");
+        bytes.truncate(4 + 3);
+        let mut inner = io::Cursor::new(bytes);
+        let mut frames = FrameReader {
+            inner: &mut inner,
+            remaining: 0,
+            done: false,
+        };
+
+        let mut payload = Vec::new();
+        let error = frames.read_to_end(&mut payload).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(payload, b"abc");
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::probe::{
+    connect_bound, effective_window, no_data, receive_bulk, tcp_rtt, Connection, Goodput,
+    Measurement, Phase, ProbeOptions,
+};
+use crate::tcp_info::{get_tcp_info, TcpInfo};
+
+// Handshakes timed before the load starts
+const IDLE_SAMPLES: usize = 5;
+
+// Time between two handshakes while the link is loaded
+const LOADED_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Queues take a moment to fill; handshakes before this are not counted as loaded
+const MAX_WARMUP: Duration = Duration::from_secs(1);
+
+// Round trip times of the link without and with load
+#[derive(Debug, Clone, Copy)]
+pub struct Responsiveness {
+    // Median handshake RTT before the load started
+    pub idle_rtt: Duration,
+    // Median handshake RTT on fresh connections while the flows saturated the link
+    pub loaded_rtt: Duration,
+    // Handshakes that completed under load
+    pub loaded_samples: usize,
+    pub flows: usize,
+}
+
+impl Responsiveness {
+    // Round trips per minute under load, as in the RPM responsiveness tests
+    pub fn rpm(&self) -> f64 {
+        let secs = self.loaded_rtt.as_secs_f64();
+        if secs > 0.0 {
+            60.0 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+// One download that keeps the link busy
+struct Flow {
+    goodput: Goodput,
+    tcp_info: Option<TcpInfo>,
+    window_size: u32,
+    bound: bool,
+}
+
+// Time handshakes on an idle link, then again on separate connections while `options.flows`
+// parallel downloads saturate it. Each download sends `options.request` and runs for
+// `options.bytes` or `options.duration`, like the bulk probe.
+pub fn measure_responsiveness(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<Measurement> {
+    let mut idle = Vec::with_capacity(IDLE_SAMPLES);
+    let mut connect_time = Duration::ZERO;
+    for _ in 0..IDLE_SAMPLES {
+        let (rtt, connection) = handshake(interface, addr, options)?;
+        connect_time = connection.connect_time;
+        idle.push(rtt);
+    }
+
+    let (flows, mut loaded) = thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.flows)
+            .map(|_| scope.spawn(|| download(interface, addr, options)))
+            .collect();
+
+        let start = Instant::now();
+        thread::sleep(MAX_WARMUP.min(options.duration / 4));
+        let mut loaded = Vec::new();
+        while start.elapsed() < options.duration && !handles.iter().all(|h| h.is_finished()) {
+            // Handshakes that time out under load are left out; at least one has to
+            // complete to report a loaded RTT
+            if let Ok((rtt, _)) = handshake(interface, addr, options) {
+                loaded.push(rtt);
+            }
+            thread::sleep(LOADED_SAMPLE_INTERVAL);
+        }
+
+        let flows: io::Result<Vec<Flow>> = handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Flow panicked")))
+            })
+            .collect();
+        (flows, loaded)
+    });
+    let flows = flows?;
+
+    if loaded.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "No handshake completed while the link was loaded",
+        ));
+    }
+    let goodput = combine(flows.iter().map(|flow| &flow.goodput));
+    if goodput.total.bytes == 0 {
+        return Err(no_data(&goodput, options.duration));
+    }
+
+    let responsiveness = Responsiveness {
+        idle_rtt: median(&mut idle),
+        loaded_rtt: median(&mut loaded),
+        loaded_samples: loaded.len(),
+        flows: flows.len(),
+    };
+    let first = &flows[0];
+    Ok(Measurement {
+        rtt: responsiveness.idle_rtt,
+        connect_time,
+        bound: flows.iter().all(|flow| flow.bound),
+        window_size: first.window_size,
+        tcp_info: first.tcp_info.clone(),
+        goodput: Some(goodput),
+        http: None,
+        reflector: None,
+        responsiveness: Some(responsiveness),
+    })
+}
+
+// Connect once and take the RTT the kernel measured for the handshake
+fn handshake(
+    interface: &str,
+    addr: SocketAddr,
+    options: &ProbeOptions,
+) -> io::Result<(Duration, Connection)> {
+    let connection = connect_bound(interface, addr, options)?;
+    let tcp_info = get_tcp_info(connection.socket.as_raw_fd());
+    let rtt = tcp_rtt(&tcp_info).unwrap_or(connection.connect_time);
+    Ok((rtt, connection))
+}
+
+fn download(interface: &str, addr: SocketAddr, options: &ProbeOptions) -> io::Result<Flow> {
+    let Connection { socket, bound, .. } = connect_bound(interface, addr, options)?;
+    let fd = socket.as_raw_fd();
+    let mut stream: std::net::TcpStream = socket.into();
+    stream.set_read_timeout(Some(options.duration))?;
+    if let Some(request) = &options.request {
+        stream.write_all(request)?;
+    }
+    let goodput = receive_bulk(&mut stream, options.bytes, options.duration)?;
+    Ok(Flow {
+        goodput,
+        tcp_info: get_tcp_info(fd),
+        window_size: effective_window(fd)?,
+        bound,
+    })
+}
+
+// The flows ran side by side: their bytes add up over the longest of their durations
+fn combine<'a>(goodputs: impl Iterator<Item = &'a Goodput> + Clone) -> Goodput {
+    let phase = |pick: fn(&Goodput) -> Phase| Phase {
+        bytes: goodputs.clone().map(|g| pick(g).bytes).sum(),
+        duration: goodputs
+            .clone()
+            .map(|g| pick(g).duration)
+            .max()
+            .unwrap_or_default(),
+    };
+    Goodput {
+        total: phase(|g| g.total),
+        ramp_up: phase(|g| g.ramp_up),
+        steady: phase(|g| g.steady),
+    }
+}
+
+fn median(samples: &mut [Duration]) -> Duration {
+    samples.sort_unstable();
+    samples[samples.len() / 2]
+}
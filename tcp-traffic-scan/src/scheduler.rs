@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::aggregate::combine;
+use crate::config::Target;
+use crate::probe::FailureReason;
+
+// Outcome of one probe as shown in the bar output
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    // Median bandwidth of the recent probes of the pair without outliers in bps, None when
+    // the probe failed; the sample of this probe alone is only part of `summary`
+    pub bandwidth_bps: Option<f64>,
+    // Bar entry, e.g. "1.1.1.1:150500000bps(rtt:...)" or "1.1.1.1:ERR"
+    pub summary: String,
+    // Why the probe failed, None on success
+    pub failure: Option<FailureReason>,
+}
+
+// Exports what a probe found and turns it into its outcome. Runs on the async side once the
+// blocking probe has returned within its deadline, so a probe that overran its timeout never
+// writes metrics after the timeout was recorded.
+pub type Publish = Box<dyn FnOnce(&Target) -> Outcome + Send>;
+
+// Blocking probe for one (interface, server) pair
+pub type ProbeFn = Arc<dyn Fn(&Target) -> Publish + Send + Sync>;
+
+// Called with every finished probe, including those that timed out or panicked
+pub type OutcomeFn = Arc<dyn Fn(&Target, &Outcome) + Send + Sync>;
+
+// Called by the reporter with each interface, the aggregate of its servers' median bandwidth
+// (None when none of its latest probes succeeded) and the share of each server in it
+pub type ReportFn = Arc<dyn Fn(&str, Option<f64>, &[(String, f64)]) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    // Time between two bar outputs / average updates
+    pub report_interval: Duration,
+    // Upper bound on probes running at the same time
+    pub max_concurrency: usize,
+}
+
+pub type PairKey = (String, String);
+
+fn pair_key(target: &Target) -> PairKey {
+    (target.interface.clone(), target.server.clone())
+}
+
+// New probe plan handed to a running scheduler
+pub struct Reload {
+    pub targets: Vec<Target>,
+    pub schedule: Schedule,
+    pub reply: oneshot::Sender<ReloadSummary>,
+}
+
+// Pairs touched by a reload
+#[derive(Debug, Default)]
+pub struct ReloadSummary {
+    pub added: Vec<PairKey>,
+    pub removed: Vec<PairKey>,
+    // Pairs whose settings changed; they are restarted with the new settings
+    pub changed: Vec<PairKey>,
+}
+
+impl fmt::Display for ReloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs = |keys: &[PairKey]| {
+            keys.iter()
+                .map(|(interface, server)| format!("{} -> {}", interface, server))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )?;
+        for (label, keys) in [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ] {
+            if !keys.is_empty() {
+                write!(f, "\n  {}: {}", label, pairs(keys))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs every (interface, server) pair on its own schedule and prints the bar output
+pub struct Scheduler {
+    targets: Vec<Target>,
+    schedule: Schedule,
+    probe: ProbeFn,
+    on_outcome: OutcomeFn,
+    report: ReportFn,
+    semaphore: Arc<Semaphore>,
+    latest: Arc<Mutex<HashMap<PairKey, Outcome>>>,
+    tasks: HashMap<PairKey, JoinHandle<()>>,
+    // Set while a blocking probe of the pair runs, kept across restarts of its task
+    in_flight: HashMap<PairKey, Arc<AtomicBool>>,
+}
+
+// Clears the in-flight flag of a pair when its blocking probe returns or panics
+struct InFlight(Arc<AtomicBool>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Scheduler {
+    pub fn new(
+        targets: Vec<Target>,
+        schedule: Schedule,
+        probe: ProbeFn,
+        on_outcome: OutcomeFn,
+        report: ReportFn,
+    ) -> Self {
+        Scheduler {
+            targets,
+            schedule,
+            probe,
+            on_outcome,
+            report,
+            semaphore: Arc::new(Semaphore::new(schedule.max_concurrency.max(1))),
+            latest: Arc::new(Mutex::new(HashMap::new())),
+            tasks: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    // Probe all pairs and print one bar per interface every interval until `running` is cleared.
+    // Plans received on `reloads` replace the current one without touching unchanged pairs.
+    pub async fn run(mut self, running: Arc<AtomicBool>, mut reloads: mpsc::Receiver<Reload>) {
+        // Spread the first probes over one interval instead of firing them all at once
+        let count = self.targets.len().max(1) as u32;
+        for (index, target) in self.targets.clone().into_iter().enumerate() {
+            let offset = target.interval / count * index as u32;
+            let key = pair_key(&target);
+            let handle = self.spawn_pair(target, offset, running.clone());
+            self.tasks.insert(key, handle);
+        }
+
+        let mut ticker = report_ticker(self.schedule.report_interval).await;
+
+        while running.load(Ordering::SeqCst) {
+            tokio::select! {
+                _ = ticker.tick() => self.print_report(),
+                Some(reload) = reloads.recv() => {
+                    let report_interval = self.schedule.report_interval;
+                    let summary = self.apply(reload.targets, reload.schedule, &running).await;
+                    if self.schedule.report_interval != report_interval {
+                        ticker = report_ticker(self.schedule.report_interval).await;
+                    }
+                    let _ = reload.reply.send(summary);
+                }
+                _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+            }
+        }
+
+        for (_, handle) in self.tasks.drain() {
+            handle.abort();
+        }
+    }
+
+    // Reconcile the running tasks with a new plan: stop removed pairs, restart changed ones
+    // and start new ones. Unchanged pairs keep their schedule and their last result.
+    // Returns once the stopped tasks are gone, so the caller can drop their series without
+    // a probe that was still in flight writing them back.
+    async fn apply(
+        &mut self,
+        targets: Vec<Target>,
+        schedule: Schedule,
+        running: &Arc<AtomicBool>,
+    ) -> ReloadSummary {
+        let mut summary = ReloadSummary::default();
+        let mut stopped = Vec::new();
+
+        let keys: HashSet<PairKey> = targets.iter().map(pair_key).collect();
+        for old in &self.targets {
+            let key = pair_key(old);
+            if !keys.contains(&key) {
+                if let Some(handle) = self.tasks.remove(&key) {
+                    handle.abort();
+                    stopped.push(handle);
+                }
+                self.latest.lock().unwrap().remove(&key);
+                self.in_flight.remove(&key);
+                summary.removed.push(key);
+            }
+        }
+
+        // Running tasks hold on to the semaphore they were started with, so a new
+        // concurrency limit only takes effect once every pair is restarted
+        let restart_all = schedule.max_concurrency != self.schedule.max_concurrency;
+        if restart_all {
+            self.semaphore = Arc::new(Semaphore::new(schedule.max_concurrency.max(1)));
+        }
+        self.schedule = schedule;
+
+        let old_targets: HashMap<PairKey, Target> = self
+            .targets
+            .drain(..)
+            .map(|target| (pair_key(&target), target))
+            .collect();
+        let count = targets.len().max(1) as u32;
+        for (index, target) in targets.iter().enumerate() {
+            let key = pair_key(target);
+            match old_targets.get(&key) {
+                Some(old) if old == target && !restart_all => continue,
+                Some(old) => {
+                    if let Some(handle) = self.tasks.remove(&key) {
+                        handle.abort();
+                        stopped.push(handle);
+                    }
+                    if old != target {
+                        summary.changed.push(key.clone());
+                    }
+                }
+                None => summary.added.push(key.clone()),
+            }
+            let offset = target.interval / count * index as u32;
+            let handle = self.spawn_pair(target.clone(), offset, running.clone());
+            self.tasks.insert(key, handle);
+        }
+
+        self.targets = targets;
+
+        // An aborted task stops at its next await; a blocking probe it was waiting for is
+        // left to finish on its own, its result is never published
+        for handle in stopped {
+            let _ = handle.await;
+        }
+        summary
+    }
+
+    fn spawn_pair(
+        &mut self,
+        target: Target,
+        offset: Duration,
+        running: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let in_flight = self.in_flight.entry(pair_key(&target)).or_default().clone();
+        let target = Arc::new(target);
+        let probe = self.probe.clone();
+        let on_outcome = self.on_outcome.clone();
+        let semaphore = self.semaphore.clone();
+        let latest = self.latest.clone();
+
+        tokio::spawn(async move {
+            let interface = &target.interface;
+            let server = &target.server;
+            let mut ticker = tokio::time::interval_at(Instant::now() + offset, target.interval);
+            // A probe that overruns its slot pushes the schedule back rather than bursting
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            while running.load(Ordering::SeqCst) {
+                ticker.tick().await;
+
+                // A probe that overran its deadline is still transferring; a second one on
+                // the same link would distort both, so this slot counts as timed out too
+                let outcome = if in_flight.load(Ordering::SeqCst) {
+                    eprintln!(
+                        "Probe of {} on {} is still running past its deadline, skipping",
+                        server, interface
+                    );
+                    timed_out(server)
+                } else {
+                    let permit = match semaphore.clone().acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => return,
+                    };
+
+                    in_flight.store(true, Ordering::SeqCst);
+                    let task = {
+                        let probe = probe.clone();
+                        let target = target.clone();
+                        let in_flight = InFlight(in_flight.clone());
+                        tokio::task::spawn_blocking(move || {
+                            // The permit is released only once the blocking probe really returns
+                            let _permit = permit;
+                            let _in_flight = in_flight;
+                            probe(&target)
+                        })
+                    };
+
+                    match tokio::time::timeout(target.probe_timeout, task).await {
+                        Ok(Ok(publish)) => publish(&target),
+                        Ok(Err(e)) => {
+                            eprintln!("Probe of {} on {} panicked: {}", server, interface, e);
+                            Outcome {
+                                bandwidth_bps: None,
+                                summary: format!("{}:ERR", server),
+                                failure: Some(FailureReason::Other),
+                            }
+                        }
+                        // The blocking probe keeps running; what it returns is dropped unpublished
+                        Err(_) => {
+                            eprintln!(
+                                "Probe of {} on {} exceeded its {:?} deadline",
+                                server, interface, target.probe_timeout
+                            );
+                            timed_out(server)
+                        }
+                    }
+                };
+
+                on_outcome(&target, &outcome);
+                latest
+                    .lock()
+                    .unwrap()
+                    .insert((interface.clone(), server.clone()), outcome);
+            }
+        })
+    }
+
+    fn print_report(&self) {
+        let latest = self.latest.lock().unwrap();
+
+        // Interfaces in configuration order
+        let mut interfaces: Vec<&str> = Vec::new();
+        for target in &self.targets {
+            if !interfaces.contains(&target.interface.as_str()) {
+                interfaces.push(&target.interface);
+            }
+        }
+
+        for interface in interfaces {
+            let mut results = Vec::new();
+            // Servers that answered with their (bandwidth, priority)
+            let mut answered = Vec::new();
+            let mut samples = Vec::new();
+
+            let servers: Vec<&Target> = self
+                .targets
+                .iter()
+                .filter(|t| t.interface == interface)
+                .collect();
+            for target in &servers {
+                if let Some(outcome) =
+                    latest.get(&(target.interface.clone(), target.server.clone()))
+                {
+                    if let Some(bps) = outcome.bandwidth_bps {
+                        answered.push(target.server.as_str());
+                        samples.push((bps, target.priority));
+                    }
+                    results.push(outcome.summary.clone());
+                }
+            }
+
+            if results.is_empty() {
+                continue;
+            }
+
+            // Combine the servers into the bandwidth of this interface
+            match combine(servers[0].aggregate, &samples) {
+                Some((bandwidth, shares)) => {
+                    let shares: Vec<(String, f64)> = servers
+                        .iter()
+                        .map(|target| {
+                            let share = answered
+                                .iter()
+                                .position(|server| *server == target.server)
+                                .map_or(0.0, |i| shares[i]);
+                            (target.server.clone(), share)
+                        })
+                        .collect();
+                    (self.report)(interface, Some(bandwidth), &shares);
+                    results.push(format!("avg:{:.0}bps", bandwidth));
+                }
+                None => (self.report)(interface, None, &[]),
+            }
+
+            // Print interface results in bar format
+            println!("{}: |{}|", interface, results.join("|"));
+        }
+
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn timed_out(server: &str) -> Outcome {
+    Outcome {
+        bandwidth_bps: None,
+        summary: format!("{}:TIMEOUT", server),
+        failure: Some(FailureReason::Timeout),
+    }
+}
+
+// Ticker for the bar output whose first tick is one full interval away
+async fn report_ticker(report_interval: Duration) -> tokio::time::Interval {
+    let mut ticker = tokio::time::interval(report_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately; skip it so the first report has results
+    ticker.tick().await;
+    ticker
+}
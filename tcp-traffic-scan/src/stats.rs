@@ -0,0 +1,149 @@
+// Rolling window of bandwidth samples per interface/server pair. A single probe can be far
+// off (one SYN retransmit doubles the RTT), so samples further than `outlier_mad` scaled
+// median absolute deviations from the median of the window are left out of the statistics.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Fewer samples than this are too few to tell an outlier
+const MIN_SAMPLES_FOR_OUTLIERS: usize = 5;
+
+// MAD of a normal distribution times this is its standard deviation
+const MAD_SCALE: f64 = 1.4826;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatsSettings {
+    // Samples older than this are dropped
+    pub window: Duration,
+    // Weight of the newest sample in the EWMA
+    pub ewma_alpha: f64,
+    // Outlier threshold in scaled MADs, None keeps every sample
+    pub outlier_mad: Option<f64>,
+}
+
+// Statistics over the accepted samples of a window
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    pub median: f64,
+    pub p10: f64,
+    pub p90: f64,
+    pub ewma: f64,
+    // Samples in the window, outliers included
+    pub samples: usize,
+    pub outliers: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Window {
+    samples: VecDeque<(Instant, f64)>,
+    ewma: Option<f64>,
+}
+
+impl Window {
+    // Add a sample taken at `now` and summarize the window
+    pub fn push(&mut self, now: Instant, value: f64, settings: &StatsSettings) -> Summary {
+        while let Some(&(taken, _)) = self.samples.front() {
+            if now.duration_since(taken) < settings.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        // The EWMA only follows samples that fit the window they arrive in
+        let values: Vec<f64> = self.samples.iter().map(|&(_, v)| v).collect();
+        let outlier = outlier_bounds(&values, settings).is_some_and(|b| !within(value, b));
+        if !outlier {
+            self.ewma = Some(match self.ewma {
+                Some(ewma) => settings.ewma_alpha * value + (1.0 - settings.ewma_alpha) * ewma,
+                None => value,
+            });
+        }
+        self.samples.push_back((now, value));
+
+        // Samples are re-judged against the whole window, so a lasting change of the
+        // bandwidth stops counting as outliers once it makes up half of the window
+        let values: Vec<f64> = self.samples.iter().map(|&(_, v)| v).collect();
+        let mut accepted: Vec<f64> = match outlier_bounds(&values, settings) {
+            Some(bounds) => values
+                .iter()
+                .copied()
+                .filter(|&v| within(v, bounds))
+                .collect(),
+            None => values.clone(),
+        };
+        // With an even count the median lies between two samples; a tight threshold can
+        // then reject all of them, which says nothing about any single one
+        if accepted.is_empty() {
+            accepted = values.clone();
+        }
+        accepted.sort_by(f64::total_cmp);
+
+        Summary {
+            median: percentile(&accepted, 0.5),
+            p10: percentile(&accepted, 0.1),
+            p90: percentile(&accepted, 0.9),
+            ewma: self.ewma.unwrap_or(value),
+            samples: values.len(),
+            outliers: values.len() - accepted.len(),
+        }
+    }
+}
+
+fn within(value: f64, (low, high): (f64, f64)) -> bool {
+    low <= value && value <= high
+}
+
+// Range of values that are not outliers, None when outlier rejection does not apply
+fn outlier_bounds(values: &[f64], settings: &StatsSettings) -> Option<(f64, f64)> {
+    let threshold = settings.outlier_mad?;
+    if values.len() < MIN_SAMPLES_FOR_OUTLIERS {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = percentile(&sorted, 0.5);
+    let mut deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+    deviations.sort_by(f64::total_cmp);
+    // Identical samples give a MAD of 0; allow 1% of the median so any change isn't an outlier
+    let mad = percentile(&deviations, 0.5).max(median.abs() * 0.01);
+    let margin = threshold * MAD_SCALE * mad;
+    Some((median - margin, median + margin))
+}
+
+// Linear interpolation between the closest ranks of `sorted`
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = p * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tight_threshold_keeps_the_samples_when_it_rejects_all() {
+        let settings = StatsSettings {
+            window: Duration::from_secs(60),
+            ewma_alpha: 0.3,
+            outlier_mad: Some(0.01),
+        };
+        let mut window = Window::default();
+        let now = Instant::now();
+        let mut summary = None;
+        for (i, value) in [100.0, 200.0, 100.0, 200.0, 100.0, 200.0]
+            .into_iter()
+            .enumerate()
+        {
+            summary = Some(window.push(now + Duration::from_secs(i as u64), value, &settings));
+        }
+        let summary = summary.unwrap();
+        assert_eq!(summary.samples, 6);
+        assert_eq!(summary.outliers, 0);
+        assert_eq!(summary.median, 150.0);
+        assert_eq!(summary.p10, 100.0);
+        assert_eq!(summary.p90, 200.0);
+    }
+}
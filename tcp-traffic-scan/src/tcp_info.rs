@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+// Typed snapshot of the kernel's `struct tcp_info` for one socket.
+// Times are converted to `Duration`, rates are in bytes per second as the kernel reports them.
+// Fields that the running kernel does not know about are left at zero.
+#[derive(Debug, Clone, Default)]
+#[allow(dead_code)]
+pub struct TcpInfo {
+    pub state: u8,
+    pub ca_state: u8,
+    pub retransmits: u8,
+    pub probes: u8,
+    pub backoff: u8,
+    pub options: u8,
+    pub snd_wscale: u8,
+    pub rcv_wscale: u8,
+    pub delivery_rate_app_limited: bool,
+    pub fastopen_client_fail: u8,
+
+    pub rto: Duration,
+    pub ato: Duration,
+    pub snd_mss: u32,
+    pub rcv_mss: u32,
+
+    pub unacked: u32,
+    pub sacked: u32,
+    pub lost: u32,
+    pub retrans: u32,
+    pub fackets: u32,
+
+    pub last_data_sent: Duration,
+    pub last_ack_sent: Duration,
+    pub last_data_recv: Duration,
+    pub last_ack_recv: Duration,
+
+    pub pmtu: u32,
+    pub rcv_ssthresh: u32,
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    pub snd_ssthresh: u32,
+    pub snd_cwnd: u32,
+    pub advmss: u32,
+    pub reordering: u32,
+
+    pub rcv_rtt: Duration,
+    pub rcv_space: u32,
+    pub total_retrans: u32,
+
+    pub pacing_rate: u64,
+    pub max_pacing_rate: u64,
+    pub bytes_acked: u64,
+    pub bytes_received: u64,
+    pub segs_out: u32,
+    pub segs_in: u32,
+
+    pub notsent_bytes: u32,
+    pub min_rtt: Duration,
+    pub data_segs_in: u32,
+    pub data_segs_out: u32,
+
+    pub delivery_rate: u64,
+
+    pub busy_time: Duration,
+    pub rwnd_limited: Duration,
+    pub sndbuf_limited: Duration,
+
+    pub delivered: u32,
+    pub delivered_ce: u32,
+
+    pub bytes_sent: u64,
+    pub bytes_retrans: u64,
+    pub dsack_dups: u32,
+    pub reord_seen: u32,
+
+    pub rcv_ooopack: u32,
+    pub snd_wnd: u32,
+    pub rcv_wnd: u32,
+    pub rehash: u32,
+
+    pub total_rto: u16,
+    pub total_rto_recoveries: u16,
+    pub total_rto_time: Duration,
+}
+
+// Mirror of `struct tcp_info` from include/uapi/linux/tcp.h (Linux 6.7+).
+// Older kernels copy a shorter prefix; the rest stays zeroed.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct RawTcpInfo {
+    tcpi_state: u8,
+    tcpi_ca_state: u8,
+    tcpi_retransmits: u8,
+    tcpi_probes: u8,
+    tcpi_backoff: u8,
+    tcpi_options: u8,
+    tcpi_snd_wscale_rcv_wscale: u8, // snd_wscale:4, rcv_wscale:4
+    tcpi_delivery_rate_app_limited_fastopen_client_fail: u8, // app_limited:1, fastopen_client_fail:2
+
+    tcpi_rto: u32, // microseconds
+    tcpi_ato: u32, // microseconds
+    tcpi_snd_mss: u32,
+    tcpi_rcv_mss: u32,
+
+    tcpi_unacked: u32,
+    tcpi_sacked: u32,
+    tcpi_lost: u32,
+    tcpi_retrans: u32,
+    tcpi_fackets: u32,
+
+    // Times (milliseconds)
+    tcpi_last_data_sent: u32,
+    tcpi_last_ack_sent: u32,
+    tcpi_last_data_recv: u32,
+    tcpi_last_ack_recv: u32,
+
+    // Metrics
+    tcpi_pmtu: u32,
+    tcpi_rcv_ssthresh: u32,
+    tcpi_rtt: u32,    // Smoothed RTT in microseconds
+    tcpi_rttvar: u32, // RTT variance in microseconds
+    tcpi_snd_ssthresh: u32,
+    tcpi_snd_cwnd: u32,
+    tcpi_advmss: u32,
+    tcpi_reordering: u32,
+
+    tcpi_rcv_rtt: u32, // microseconds
+    tcpi_rcv_space: u32,
+
+    tcpi_total_retrans: u32,
+
+    tcpi_pacing_rate: u64,
+    tcpi_max_pacing_rate: u64,
+    tcpi_bytes_acked: u64,
+    tcpi_bytes_received: u64,
+    tcpi_segs_out: u32,
+    tcpi_segs_in: u32,
+
+    tcpi_notsent_bytes: u32,
+    tcpi_min_rtt: u32, // microseconds
+    tcpi_data_segs_in: u32,
+    tcpi_data_segs_out: u32,
+
+    tcpi_delivery_rate: u64,
+
+    tcpi_busy_time: u64,      // microseconds
+    tcpi_rwnd_limited: u64,   // microseconds
+    tcpi_sndbuf_limited: u64, // microseconds
+
+    tcpi_delivered: u32,
+    tcpi_delivered_ce: u32,
+
+    tcpi_bytes_sent: u64,
+    tcpi_bytes_retrans: u64,
+    tcpi_dsack_dups: u32,
+    tcpi_reord_seen: u32,
+
+    tcpi_rcv_ooopack: u32,
+
+    tcpi_snd_wnd: u32,
+    tcpi_rcv_wnd: u32,
+
+    tcpi_rehash: u32,
+
+    tcpi_total_rto: u16,
+    tcpi_total_rto_recoveries: u16,
+    tcpi_total_rto_time: u32, // milliseconds
+}
+
+#[cfg(target_os = "linux")]
+impl From<&RawTcpInfo> for TcpInfo {
+    fn from(raw: &RawTcpInfo) -> Self {
+        let us = |v: u32| Duration::from_micros(v as u64);
+        let ms = |v: u32| Duration::from_millis(v as u64);
+        let flags = raw.tcpi_delivery_rate_app_limited_fastopen_client_fail;
+
+        TcpInfo {
+            state: raw.tcpi_state,
+            ca_state: raw.tcpi_ca_state,
+            retransmits: raw.tcpi_retransmits,
+            probes: raw.tcpi_probes,
+            backoff: raw.tcpi_backoff,
+            options: raw.tcpi_options,
+            snd_wscale: raw.tcpi_snd_wscale_rcv_wscale & 0x0f,
+            rcv_wscale: raw.tcpi_snd_wscale_rcv_wscale >> 4,
+            delivery_rate_app_limited: flags & 0x01 != 0,
+            fastopen_client_fail: (flags >> 1) & 0x03,
+
+            rto: us(raw.tcpi_rto),
+            ato: us(raw.tcpi_ato),
+            snd_mss: raw.tcpi_snd_mss,
+            rcv_mss: raw.tcpi_rcv_mss,
+
+            unacked: raw.tcpi_unacked,
+            sacked: raw.tcpi_sacked,
+            lost: raw.tcpi_lost,
+            retrans: raw.tcpi_retrans,
+            fackets: raw.tcpi_fackets,
+
+            last_data_sent: ms(raw.tcpi_last_data_sent),
+            last_ack_sent: ms(raw.tcpi_last_ack_sent),
+            last_data_recv: ms(raw.tcpi_last_data_recv),
+            last_ack_recv: ms(raw.tcpi_last_ack_recv),
+
+            pmtu: raw.tcpi_pmtu,
+            rcv_ssthresh: raw.tcpi_rcv_ssthresh,
+            rtt: us(raw.tcpi_rtt),
+            rttvar: us(raw.tcpi_rttvar),
+            snd_ssthresh: raw.tcpi_snd_ssthresh,
+            snd_cwnd: raw.tcpi_snd_cwnd,
+            advmss: raw.tcpi_advmss,
+            reordering: raw.tcpi_reordering,
+
+            rcv_rtt: us(raw.tcpi_rcv_rtt),
+            rcv_space: raw.tcpi_rcv_space,
+            total_retrans: raw.tcpi_total_retrans,
+
+            pacing_rate: raw.tcpi_pacing_rate,
+            max_pacing_rate: raw.tcpi_max_pacing_rate,
+            bytes_acked: raw.tcpi_bytes_acked,
+            bytes_received: raw.tcpi_bytes_received,
+            segs_out: raw.tcpi_segs_out,
+            segs_in: raw.tcpi_segs_in,
+
+            notsent_bytes: raw.tcpi_notsent_bytes,
+            min_rtt: us(raw.tcpi_min_rtt),
+            data_segs_in: raw.tcpi_data_segs_in,
+            data_segs_out: raw.tcpi_data_segs_out,
+
+            delivery_rate: raw.tcpi_delivery_rate,
+
+            busy_time: Duration::from_micros(raw.tcpi_busy_time),
+            rwnd_limited: Duration::from_micros(raw.tcpi_rwnd_limited),
+            sndbuf_limited: Duration::from_micros(raw.tcpi_sndbuf_limited),
+
+            delivered: raw.tcpi_delivered,
+            delivered_ce: raw.tcpi_delivered_ce,
+
+            bytes_sent: raw.tcpi_bytes_sent,
+            bytes_retrans: raw.tcpi_bytes_retrans,
+            dsack_dups: raw.tcpi_dsack_dups,
+            reord_seen: raw.tcpi_reord_seen,
+
+            rcv_ooopack: raw.tcpi_rcv_ooopack,
+            snd_wnd: raw.tcpi_snd_wnd,
+            rcv_wnd: raw.tcpi_rcv_wnd,
+            rehash: raw.tcpi_rehash,
+
+            total_rto: raw.tcpi_total_rto,
+            total_rto_recoveries: raw.tcpi_total_rto_recoveries,
+            total_rto_time: ms(raw.tcpi_total_rto_time),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_tcp_info(fd: i32) -> Option<TcpInfo> {
+    let mut raw: RawTcpInfo = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<RawTcpInfo>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if result == 0 {
+        Some(TcpInfo::from(&raw))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_tcp_info(_fd: i32) -> Option<TcpInfo> {
+    // TCP_INFO is Linux-specific; return None on other platforms
+    None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_fields_are_decoded() {
+        let mut raw: RawTcpInfo = unsafe { std::mem::zeroed() };
+        // snd_wscale in the low nibble, rcv_wscale in the high one
+        raw.tcpi_snd_wscale_rcv_wscale = 0x7e;
+        // app_limited, then two bits of fastopen_client_fail
+        raw.tcpi_delivery_rate_app_limited_fastopen_client_fail = 0b101;
+
+        let info = TcpInfo::from(&raw);
+        assert_eq!(info.snd_wscale, 14);
+        assert_eq!(info.rcv_wscale, 7);
+        assert!(info.delivery_rate_app_limited);
+        assert_eq!(info.fastopen_client_fail, 2);
+
+        raw.tcpi_delivery_rate_app_limited_fastopen_client_fail = 0b110;
+        let info = TcpInfo::from(&raw);
+        assert!(!info.delivery_rate_app_limited);
+        assert_eq!(info.fastopen_client_fail, 3);
+    }
+
+    #[test]
+    fn connected_socket_has_tcp_info() {
+        use std::os::unix::io::AsRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let info = get_tcp_info(stream.as_raw_fd()).unwrap();
+        // TCP_ESTABLISHED
+        assert_eq!(info.state, 1);
+        assert!(info.snd_mss > 0);
+        assert!(info.snd_cwnd > 0);
+    }
+}
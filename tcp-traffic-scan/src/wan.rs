@@ -0,0 +1,149 @@
+use prometheus::proto::{LabelPair, MetricFamily};
+use std::collections::BTreeMap;
+
+// Logical WAN names (wan0, ppp-backup, ...) and the interfaces they stand for.
+// Aliases are one-to-one so the `wan` label of a series is never ambiguous.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WanAliases {
+    // WAN name -> interface
+    interfaces: BTreeMap<String, String>,
+}
+
+impl WanAliases {
+    // wan0 -> eth0 and wan1 -> eth1, used when no alias is configured at all
+    pub fn builtin() -> WanAliases {
+        WanAliases {
+            interfaces: BTreeMap::from([
+                ("wan0".to_string(), "eth0".to_string()),
+                ("wan1".to_string(), "eth1".to_string()),
+            ]),
+        }
+    }
+
+    // Build the table from (WAN name, interface) pairs, rejecting ambiguous ones
+    pub fn new(pairs: impl IntoIterator<Item = (String, String)>) -> Result<WanAliases, String> {
+        let mut interfaces: BTreeMap<String, String> = BTreeMap::new();
+        for (wan, interface) in pairs {
+            if wan.is_empty() || interface.is_empty() {
+                return Err(format!("Empty WAN alias '{}' -> '{}'", wan, interface));
+            }
+            if let Some(old) = interfaces.get(&wan) {
+                if old != &interface {
+                    return Err(format!(
+                        "WAN alias {} points to both {} and {}",
+                        wan, old, interface
+                    ));
+                }
+            }
+            if let Some((other, _)) = interfaces
+                .iter()
+                .find(|(other, i)| **i == interface && **other != wan)
+            {
+                return Err(format!(
+                    "Interface {} has two WAN aliases, {} and {}",
+                    interface, other, wan
+                ));
+            }
+            interfaces.insert(wan, interface);
+        }
+        for (wan, interface) in &interfaces {
+            if wan != interface && interfaces.values().any(|i| i == wan) {
+                return Err(format!(
+                    "WAN alias {} -> {} is also the name of an aliased interface",
+                    wan, interface
+                ));
+            }
+        }
+        Ok(WanAliases { interfaces })
+    }
+
+    // Interface behind `nic`, which may be a WAN name or already an interface name
+    pub fn interface(&self, nic: &str) -> String {
+        self.interfaces
+            .get(nic)
+            .cloned()
+            .unwrap_or_else(|| nic.to_string())
+    }
+
+    // WAN name of `interface`; an interface without alias is its own WAN name
+    pub fn wan<'a>(&'a self, interface: &'a str) -> &'a str {
+        self.interfaces
+            .iter()
+            .find(|(_, i)| *i == interface)
+            .map(|(wan, _)| wan.as_str())
+            .unwrap_or(interface)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.interfaces
+            .iter()
+            .map(|(wan, interface)| (wan.as_str(), interface.as_str()))
+    }
+}
+
+// Add a `wan` label to every series that has an `interface` label. Done on the gathered
+// families so the label follows alias changes on reload without touching stored series.
+pub fn label_families(families: &mut [MetricFamily], aliases: &WanAliases) {
+    for family in families {
+        for metric in family.mut_metric().iter_mut() {
+            let labels = metric.mut_label();
+            if labels.iter().any(|label| label.get_name() == "wan") {
+                continue;
+            }
+            let Some(interface) = labels
+                .iter()
+                .find(|label| label.get_name() == "interface")
+                .map(|label| label.get_value().to_string())
+            else {
+                continue;
+            };
+
+            let mut wan = LabelPair::default();
+            wan.set_name("wan".to_string());
+            wan.set_value(aliases.wan(&interface).to_string());
+            labels.push(wan);
+            labels.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> Result<WanAliases, String> {
+        WanAliases::new(
+            pairs
+                .iter()
+                .map(|(wan, interface)| (wan.to_string(), interface.to_string())),
+        )
+    }
+
+    #[test]
+    fn aliases_map_both_ways() {
+        let aliases = aliases(&[("wan0", "eth0"), ("backup", "ppp0")]).unwrap();
+
+        assert_eq!(aliases.interface("wan0"), "eth0");
+        assert_eq!(aliases.interface("eth0"), "eth0");
+        assert_eq!(aliases.wan("ppp0"), "backup");
+        assert_eq!(aliases.wan("eth9"), "eth9");
+    }
+
+    #[test]
+    fn repeated_identical_pairs_are_accepted() {
+        assert!(aliases(&[("wan0", "eth0"), ("wan0", "eth0")]).is_ok());
+        assert!(aliases(&[("eth0", "eth0")]).is_ok());
+    }
+
+    #[test]
+    fn ambiguous_aliases_are_rejected() {
+        // One WAN name for two interfaces
+        assert!(aliases(&[("wan0", "eth0"), ("wan0", "eth1")]).is_err());
+        // Two WAN names for one interface
+        assert!(aliases(&[("wan0", "eth0"), ("wan1", "eth0")]).is_err());
+        // A WAN name that is also an aliased interface
+        assert!(aliases(&[("wan0", "eth0"), ("eth0", "eth1")]).is_err());
+        assert!(aliases(&[("", "eth0")]).is_err());
+        assert!(aliases(&[("wan0", "")]).is_err());
+    }
+}